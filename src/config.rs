@@ -1,5 +1,5 @@
 use crate::publishers::PublisherType;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::sync::Arc;
 
 #[cfg(feature = "azure_storage_queues")]
@@ -16,6 +16,7 @@ pub struct Settings {
     azure_msal_credentials: Option<(String, String, String, String)>
 }
 impl Settings {
+    #[cfg(feature = "azure_storage_queues")]
     pub async fn init(&mut self) {
         self.azure_msal_credentials = match self.prefect_use_msal_auth {
             Some(flag) => {
@@ -31,14 +32,26 @@ impl Settings {
             None => None
         }
     }
+    #[cfg(not(feature = "azure_storage_queues"))]
+    pub async fn init(&mut self) {}
     #[cfg(feature = "azure_storage_queues")]
     pub fn get_azure_credentials(&self) -> Option<&(String, String, String, String)> {
         self.azure_msal_credentials.as_ref()
     }
 }
 
+/// Rejects publisher configs that serde accepts but the publisher could not run with
+fn validated_threads<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PublisherType>, D::Error> {
+    let threads = Vec::<PublisherType>::deserialize(deserializer)?;
+    for thread in &threads {
+        thread.validate().map_err(serde::de::Error::custom)?;
+    }
+    Ok(threads)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigFile {
+    #[serde(deserialize_with = "validated_threads")]
     threads: Vec<PublisherType>,
     settings: Settings
 }
@@ -46,13 +59,29 @@ impl ConfigFile {
     pub async fn init(&mut self) {
         self.settings.init().await;
     }
-    pub fn iter(&self) -> std::slice::Iter<PublisherType> {
+    pub fn iter(&self) -> std::slice::Iter<'_, PublisherType> {
         self.threads.iter()
     }
     pub fn get_settings_ptr(&self) -> Arc<Settings> {
         // need to use Arc pointer as will be cloned across
         // multiple threads
-        return Arc::new(self.settings.clone())
+        Arc::new(self.settings.clone())
 
     }
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, feature = "mqtt"))]
+mod tests {
+    use super::ConfigFile;
+    use serde_json::json;
+
+    #[test]
+    fn test_rejects_invalid_publisher_config() {
+        let config = json!({
+            "threads": [{"publisher_type": "Mqtt", "host": "127.0.0.1", "port": 1883, "topics": ["sensors/#"]}],
+            "settings": {}
+        });
+        let error = serde_json::from_value::<ConfigFile>(config).expect_err("Should need a client_id");
+        assert!(error.to_string().contains("client_id"));
+    }
+}
@@ -1,22 +1,24 @@
 use std::collections::HashMap;
+use std::fmt;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use crate::routing::Route;
 
-pub trait LinkedPrefectJob {
-    // returns flow_name, deployment_name
-    fn get_flow_deployment(&self) -> (String, String);
-}
-
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Error {
     PrefectApiError(String),
-    InputError(String)
+    // only raised when reading the MSAL credentials
+    #[cfg_attr(not(feature = "azure_storage_queues"), allow(dead_code))]
+    InputError(String),
+    InvalidMessage(String)
 }
-impl Error {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::InputError(s) => format!("InputError: {}",s),
-            Self::PrefectApiError(s) => format!("PrefectApiError: {}",s)
+            Self::InputError(s) => write!(f, "InputError: {}",s),
+            Self::PrefectApiError(s) => write!(f, "PrefectApiError: {}",s),
+            Self::InvalidMessage(s) => write!(f, "InvalidMessage: {}",s)
         }
     }
 }
@@ -24,8 +26,8 @@ impl Error {
 /// The Publisher trait defines the interface for different
 /// configurations used to connect to a source
 #[async_trait]
-pub trait Publisher {
-    type PubMessage: RawMessage;
+pub trait Publisher: Send {
+    type PubMessage: RawMessage + Send;
 
     /// String representation of the queue-level identifer for the given config
     /// For example for an AzureStorageQueue -> "stroage-account-name/queue"
@@ -41,11 +43,42 @@ pub trait Publisher {
     /// Mark a task as done if applicable. Just leave an empty implementation if not required
     async fn task_done(&mut self, message: Self::PubMessage);
 
+    /// Called with the flow run once the prefect deployment was triggered. Sources that
+    /// only need to acknowledge the message can rely on the default of calling task_done
+    async fn task_triggered(&mut self, message: Self::PubMessage, _flow_run: &FlowRun) {
+        self.task_done(message).await
+    }
+
+    /// Called instead of task_done when the prefect deployment could not be triggered
+    /// so sources that support it can release the message for redelivery
+    async fn task_failed(&mut self, _message: Self::PubMessage, _error: &Error) {}
+
+    /// Called when a message cannot be turned into a QMessage. Sources that hold on to
+    /// unacknowledged messages should settle it here so it is not redelivered forever
+    async fn task_invalid(&mut self, _message: Self::PubMessage, _error: &Error) {}
+
+    /// Routing rules used to map messages onto deployments from their attributes.
+    /// Publishers without attributes can leave the default of no routes
+    fn routes(&self) -> &[Route] {
+        &[]
+    }
 
 }
 
 pub trait RawMessage {
     fn get_content_str(&self) -> String;
+
+    /// Source metadata of the message, eg. the topic it was received on, for routing
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+}
+
+/// The flow run created by triggering a deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowRun {
+    pub id: String,
+    pub name: String
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,6 +88,9 @@ pub struct QMessage {
     payload: Option<serde_json::Value>
 }
 impl QMessage {
+    pub fn new(flow_name: String, deployment_name: String, payload: Option<serde_json::Value>) -> Self {
+        Self {flow_name, deployment_name, payload}
+    }
     pub fn get_flow_parameters(&self) -> &Option<serde_json::Value> {
         &self.payload
     }
@@ -2,6 +2,7 @@ mod prefect;
 mod interfaces;
 mod config;
 mod publishers;
+mod routing;
 
 #[cfg(feature = "azure_storage_queues")]
 mod msal;
@@ -11,7 +12,7 @@ use std::io::Read;
 use std::sync::Arc;
 use tokio::task::JoinSet;
 use publishers::PublisherType;
-use interfaces::{Publisher, QMessage, RawMessage, Error};
+use interfaces::{Publisher, QMessage, Error};
 
 async fn thread_loop(
     mut publisher: impl Publisher,
@@ -23,11 +24,11 @@ async fn thread_loop(
     
     loop {
         let message = publisher.next_message().await;
-        if let None = message {
+        if message.is_none() {
             continue
         };
         println!("{}: Found message", &publisher.repr());
-        let q_message: QMessage = match serde_json::from_str(&message.as_ref().unwrap().get_content_str()) {
+        let q_message: QMessage = match routing::to_q_message(publisher.routes(), message.as_ref().unwrap()) {
             Ok(value) => value,
             Err(error) => {
                 let error_str = error.to_string();
@@ -39,6 +40,7 @@ async fn thread_loop(
                         &loop_name, error
                     );
                 }
+                publisher.task_invalid(message.unwrap(), &Error::InvalidMessage(error_str)).await;
                 continue
             }
         };
@@ -50,17 +52,20 @@ async fn thread_loop(
             &flow_name, &deployment_name, flow_parameters, &settings_ptr
         ).await;
         match trigger_result {
-            Ok(flow_run_name) => {
-                println!("{}: Successfully triggered {}/{}: {}", &loop_name, &flow_name, &deployment_name, &flow_run_name);
+            Ok(flow_run) => {
+                println!("{}: Successfully triggered {}/{}: {}", &loop_name, &flow_name, &deployment_name, &flow_run.name);
                 if let Some(params) = flow_parameters {
                     println!("{}: with parameters {}", &loop_name, params)
                 }
-                publisher.task_done(message.unwrap()).await;
+                publisher.task_triggered(message.unwrap(), &flow_run).await;
             },
-            Err(error) => println!(
-                "{}: Failed to execute prefect deployment trigger. Got {:?}", 
-                &loop_name, error
-            )
+            Err(error) => {
+                println!(
+                    "{}: Failed to execute prefect deployment trigger. Got {:?}", 
+                    &loop_name, error
+                );
+                publisher.task_failed(message.unwrap(), &error).await;
+            }
         };
     }
 }
@@ -80,11 +85,11 @@ fn config_from_str(config_str: String) -> config::ConfigFile {
 }
 
 #[tokio::main]
-async fn main() -> () {
+async fn main() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 2 {
         println!("Must only provide 1 CLI arguments [config path]");
-        return ();
+        return;
     }
     let file_path = args[1].clone();
     drop(args);
@@ -109,6 +114,186 @@ async fn main() -> () {
                 });
                 queue_config.repr()
             },
+            #[cfg(feature = "zmq")]
+            PublisherType::Zmq(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "kafka")]
+            PublisherType::Kafka(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "nats")]
+            PublisherType::Nats(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "mqtt")]
+            PublisherType::Mqtt(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "redis_streams")]
+            PublisherType::RedisStream(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "amqp")]
+            PublisherType::Amqp(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "sqs")]
+            PublisherType::Sqs(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "gcp_pubsub")]
+            PublisherType::GcpPubSub(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "azure_service_bus")]
+            PublisherType::AzureServiceBus(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "azure_event_hubs")]
+            PublisherType::AzureEventHub(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "http_webhook")]
+            PublisherType::HttpWebhook(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "git_webhook")]
+            PublisherType::GitWebhook(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "file_drop")]
+            PublisherType::FileDrop(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "socket")]
+            PublisherType::Socket(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "postgres")]
+            PublisherType::Postgres(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "sqlite")]
+            PublisherType::Sqlite(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "schedule")]
+            PublisherType::Schedule(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "websocket")]
+            PublisherType::WebSocket(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "sse")]
+            PublisherType::Sse(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
+            #[cfg(feature = "prefect_events")]
+            PublisherType::PrefectEvents(pub_config) => {
+                let pcc = pub_config.clone();
+                let settings_c = settings_ptr.clone();
+                spawn_set.spawn( async move{
+                    thread_loop(pcc, settings_c).await.unwrap();
+                });
+                pub_config.repr()
+            },
             PublisherType::StdInput(pub_config) => {
                 let pcc = pub_config.clone();
                 let settings_c = settings_ptr.clone();
@@ -124,8 +309,6 @@ async fn main() -> () {
         // let out = res.unwrap();
         res.unwrap();
     };
-    ()
-    
 }
 
 
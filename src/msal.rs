@@ -8,13 +8,12 @@ use crate::interfaces::Error;
 
 fn get_key_vault_client(
     creds: Arc<DefaultAzureCredential>,
-    vault_uri: &String
+    vault_uri: &str
 ) -> SecretClient {
-    let client = KeyvaultClient::new(
+    KeyvaultClient::new(
         vault_uri,
         creds,
-    ).unwrap().secret_client();
-    client
+    ).unwrap().secret_client()
 }
 fn get_key_or_value(env_var_name: &str) -> Result<(bool, String), Error> {
     let raw_value_result = std::env::var(env_var_name);
@@ -69,19 +68,19 @@ pub async fn get_token_credentials(
 }
 
 pub async fn get_azure_token(
-    client_id: &String,
-    client_secret: &String,
-    tenant_id: &String,
-    scope: &String,
+    client_id: &str,
+    client_secret: &str,
+    tenant_id: &str,
+    scope: &str,
 ) -> Result<String, Error> {
     let http_client = azure_core::new_http_client();
 
     let token = client_credentials_flow::perform(
         http_client.clone(),
-        &client_id,
-        &client_secret,
-        &[&scope],
-        &tenant_id,
+        client_id,
+        client_secret,
+        &[scope],
+        tenant_id,
     ).await;
     Ok(String::from(token.unwrap().access_token.secret()))
 }
\ No newline at end of file
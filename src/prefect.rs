@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use crate::interfaces::Error;
+use crate::interfaces::{Error, FlowRun};
 use crate::config;
 use std::sync::Arc;
 
@@ -23,11 +23,11 @@ struct DeploymentBody {
 }
 
 async fn get_deployment_id(
-    prefect_uri: &String,
-    token: Option<&String>, flow_name: &String, deployment_name: &String
+    prefect_uri: &str,
+    token: Option<&String>, flow_name: &str, deployment_name: &str
 ) -> Result<String, Error> {
-    let flow_like = Like {like_:flow_name.clone()};
-    let deployment_like = Like {like_:deployment_name.clone()};
+    let flow_like = Like {like_:flow_name.to_string()};
+    let deployment_like = Like {like_:deployment_name.to_string()};
     let flow_filter = Filter {name: flow_like};
     let deployment_filter = Filter {name: deployment_like};
     let body = DeploymentBody{
@@ -38,18 +38,7 @@ async fn get_deployment_id(
     if let Some(token_value) = token {
         req_builder = req_builder.header("Authorization", format!("Bearer {}", token_value))
     }
-    let json_res = req_builder
-        .json(&body)
-        .send()
-        .await.unwrap()
-        .json()
-        .await;
-    let res: serde_json::Value = match json_res {
-        Ok(v) => Ok(v),
-        Err(e) => Err(Error::PrefectApiError(
-            format!("Deployment ID call does not return JSON. Got {}. Are credentials set correctly?", e.to_string())
-        ))
-    }?;
+    let res = prefect_json(req_builder.json(&body).send().await, "Deployment ID").await?;
     let deployment_id = match res[0]["id"].as_str() {
         Some(id) => id,
         None => return Err(
@@ -61,8 +50,24 @@ async fn get_deployment_id(
     Ok(deployment_id.to_string())
 }
 
+/// Reads the JSON body of a Prefect API response, failing on transport errors and error statuses
+async fn prefect_json(
+    res: Result<reqwest::Response, reqwest::Error>, call: &str
+) -> Result<serde_json::Value, Error> {
+    let res = res.map_err(|e| Error::PrefectApiError(format!("{} call failed. Got {}", call, e)))?;
+    let status = res.status();
+    if !status.is_success() {
+        let body = res.text().await.unwrap_or_default();
+        return Err(Error::PrefectApiError(format!("{} call returned {}. Got {}", call, status, body)))
+    }
+    res.json().await.map_err(|e| Error::PrefectApiError(
+        format!("{} call does not return JSON. Got {}. Are credentials set correctly?", call, e)
+    ))
+}
+
 /// Gets a token for injection into the prefect API request headers from azure DefaultCredential
 /// or the PREFECT_API_KEY env var if present in that order.
+#[cfg_attr(not(feature = "azure_storage_queues"), allow(unused_variables))]
 async fn get_token(settings_ptr: &Arc<config::Settings>) -> Result<Option<String>, Error> {
     let mut token: Option<String> = None;
 
@@ -75,27 +80,36 @@ async fn get_token(settings_ptr: &Arc<config::Settings>) -> Result<Option<String
             token = Some(msal::get_azure_token(cid, csec, ten, scop).await?)
         } else {token = None}
     };
-    if let None = token {
-        token = match std::env::var("PREFECT_API_KEY") {
-            Ok(v) => Some(v), Err(_) => None
-        }
+    if token.is_none() {
+        token = std::env::var("PREFECT_API_KEY").ok()
     };
     Ok(token)
 }
 pub async fn trigger_prefect_deployment(
-    flow_name: &String,
-    deployment_name: &String,
+    flow_name: &str,
+    deployment_name: &str,
     flow_parameters: &Option<serde_json::Value>,
     settings_ptr: &Arc<config::Settings>
-) -> Result<String, Error> {
-    let prefect_uri = std::env::var("PREFECT_API_URL").expect(
-        "Env var PREFECT_API_URL is required for this application to run"
-    );
+) -> Result<FlowRun, Error> {
+    let prefect_uri = std::env::var("PREFECT_API_URL").map_err(|_| Error::PrefectApiError(
+        "Env var PREFECT_API_URL is required for this application to run".to_string()
+    ))?;
+    trigger_deployment_at(&prefect_uri, flow_name, deployment_name, flow_parameters, settings_ptr).await
+}
+
+/// Creates a flow run of the deployment on the Prefect API at `prefect_uri`
+pub(crate) async fn trigger_deployment_at(
+    prefect_uri: &str,
+    flow_name: &str,
+    deployment_name: &str,
+    flow_parameters: &Option<serde_json::Value>,
+    settings_ptr: &Arc<config::Settings>
+) -> Result<FlowRun, Error> {
     let token = get_token(settings_ptr).await?;
     let deployment_id = get_deployment_id(
-        &prefect_uri, token.as_ref(), flow_name, deployment_name
+        prefect_uri, token.as_ref(), flow_name, deployment_name
     ).await?;
-    let uri = format!("{}/deployments/{}/create_flow_run", &prefect_uri, &deployment_id);
+    let uri = format!("{}/deployments/{}/create_flow_run", prefect_uri, &deployment_id);
     let mut req_builder = reqwest::Client::new()
         .post(uri);
     if let Some(token_value) = token {
@@ -105,16 +119,9 @@ pub async fn trigger_prefect_deployment(
         Some(params) => serde_json::json!({"parameters": params}),
         None => serde_json::json!({})
     };
-    req_builder = req_builder.json(&body);
-    let res: serde_json::Value = req_builder  
-        .send()
-        .await.unwrap()
-        .json().await.unwrap();
-    Ok(
-        String::from(
-            res["name"]
-            .as_str()
-            .expect("Unable to get name from the returned json")
-        )
-    )
-}
\ No newline at end of file
+    let res = prefect_json(req_builder.json(&body).send().await, "Create flow run").await?;
+    let field = |name: &str| res[name].as_str().map(String::from).ok_or_else(|| Error::PrefectApiError(
+        format!("Create flow run response has no {}. Got {}", name, res)
+    ));
+    Ok(FlowRun {id: field("id")?, name: field("name")?})
+}
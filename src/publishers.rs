@@ -1,30 +1,169 @@
 use serde::{Deserialize, Serialize};
 
+#[cfg(any(
+    feature = "azure_storage_queues", feature = "azure_service_bus", feature = "azure_event_hubs"
+))]
+mod azure;
 #[cfg(feature = "azure_storage_queues")]
 mod azure_storage_queue;
-// #[cfg(feature = "zmq")]
-// mod zeromq;
+#[cfg(feature = "zmq")]
+mod zeromq;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "nats")]
+mod nats;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "redis_streams")]
+mod redis_stream;
+#[cfg(feature = "amqp")]
+mod amqp;
+#[cfg(feature = "sqs")]
+mod sqs;
+#[cfg(feature = "gcp_pubsub")]
+mod gcp_pubsub;
+#[cfg(feature = "azure_service_bus")]
+mod azure_service_bus;
+#[cfg(feature = "azure_event_hubs")]
+mod azure_event_hub;
+#[cfg(feature = "http_webhook")]
+mod http_webhook;
+#[cfg(feature = "git_webhook")]
+mod git_webhook;
+#[cfg(feature = "file_drop")]
+mod file_drop;
+#[cfg(feature = "socket")]
+mod socket;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "schedule")]
+mod schedule;
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "sse")]
+mod sse;
+#[cfg(feature = "prefect_events")]
+mod prefect_events;
 
 mod stdin;
 
 // enum to hold all the publishers that is used by serde to genrate from json
+// only built once per thread at startup so the variant sizes don't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "publisher_type")]
 pub enum PublisherType {
 
     #[cfg(feature = "azure_storage_queues")]
     AzureStorageQueue(azure_storage_queue::AzureStorageQueue),
-    // #[cfg(feature = "zmq")]
-    // Zmq(zmq::Zmq),
+    #[cfg(feature = "zmq")]
+    Zmq(zeromq::Zmq),
+    #[cfg(feature = "kafka")]
+    Kafka(kafka::Kafka),
+    #[cfg(feature = "nats")]
+    Nats(nats::Nats),
+    #[cfg(feature = "mqtt")]
+    Mqtt(mqtt::Mqtt),
+    #[cfg(feature = "redis_streams")]
+    RedisStream(redis_stream::RedisStream),
+    #[cfg(feature = "amqp")]
+    Amqp(amqp::Amqp),
+    #[cfg(feature = "sqs")]
+    Sqs(sqs::Sqs),
+    #[cfg(feature = "gcp_pubsub")]
+    GcpPubSub(gcp_pubsub::GcpPubSub),
+    #[cfg(feature = "azure_service_bus")]
+    AzureServiceBus(azure_service_bus::AzureServiceBus),
+    #[cfg(feature = "azure_event_hubs")]
+    AzureEventHub(azure_event_hub::AzureEventHub),
+    #[cfg(feature = "http_webhook")]
+    HttpWebhook(http_webhook::HttpWebhook),
+    #[cfg(feature = "git_webhook")]
+    GitWebhook(git_webhook::GitWebhook),
+    #[cfg(feature = "file_drop")]
+    FileDrop(file_drop::FileDrop),
+    #[cfg(feature = "socket")]
+    Socket(socket::Socket),
+    #[cfg(feature = "postgres")]
+    Postgres(postgres::Postgres),
+    #[cfg(feature = "sqlite")]
+    Sqlite(sqlite::Sqlite),
+    #[cfg(feature = "schedule")]
+    Schedule(schedule::Schedule),
+    #[cfg(feature = "websocket")]
+    WebSocket(websocket::WebSocket),
+    #[cfg(feature = "sse")]
+    Sse(sse::Sse),
+    #[cfg(feature = "prefect_events")]
+    PrefectEvents(prefect_events::PrefectEvents),
 
     StdInput(stdin::StdInput)
 }
+impl PublisherType {
+    /// Checks the parts of a publisher's config that serde cannot, so that a bad config
+    /// is rejected when it is loaded rather than crashing the router once it has started
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            #[cfg(feature = "azure_storage_queues")]
+            Self::AzureStorageQueue(pub_config) => pub_config.validate(),
+            #[cfg(feature = "mqtt")]
+            Self::Mqtt(pub_config) => pub_config.validate(),
+            #[cfg(feature = "http_webhook")]
+            Self::HttpWebhook(pub_config) => pub_config.validate(),
+            #[cfg(feature = "git_webhook")]
+            Self::GitWebhook(pub_config) => pub_config.validate(),
+            _ => Ok(())
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
 
     #[cfg(feature = "azure_storage_queues")]
     use super::azure_storage_queue::AzureStorageQueue;
+    #[cfg(feature = "zmq")]
+    use super::zeromq::Zmq;
+    #[cfg(feature = "kafka")]
+    use super::kafka::Kafka;
+    #[cfg(feature = "nats")]
+    use super::nats::Nats;
+    #[cfg(feature = "mqtt")]
+    use super::mqtt::Mqtt;
+    #[cfg(feature = "redis_streams")]
+    use super::redis_stream::RedisStream;
+    #[cfg(feature = "amqp")]
+    use super::amqp::Amqp;
+    #[cfg(feature = "sqs")]
+    use super::sqs::Sqs;
+    #[cfg(feature = "gcp_pubsub")]
+    use super::gcp_pubsub::GcpPubSub;
+    #[cfg(feature = "azure_service_bus")]
+    use super::azure_service_bus::AzureServiceBus;
+    #[cfg(feature = "azure_event_hubs")]
+    use super::azure_event_hub::AzureEventHub;
+    #[cfg(feature = "http_webhook")]
+    use super::http_webhook::HttpWebhook;
+    #[cfg(feature = "git_webhook")]
+    use super::git_webhook::GitWebhook;
+    #[cfg(feature = "file_drop")]
+    use super::file_drop::FileDrop;
+    #[cfg(feature = "socket")]
+    use super::socket::Socket;
+    #[cfg(feature = "postgres")]
+    use super::postgres::Postgres;
+    #[cfg(feature = "sqlite")]
+    use super::sqlite::Sqlite;
+    #[cfg(feature = "schedule")]
+    use super::schedule::Schedule;
+    #[cfg(feature = "websocket")]
+    use super::websocket::WebSocket;
+    #[cfg(feature = "sse")]
+    use super::sse::Sse;
+    #[cfg(feature = "prefect_events")]
+    use super::prefect_events::PrefectEvents;
     use super::PublisherType;
     use serde_json::json;
 
@@ -40,7 +179,9 @@ mod tests {
         );
         let _stdin = match azure_publisher {
             PublisherType::StdInput(v) => v,
-            _ => panic!("Not expecting any other type other than AzureStorageQueue")
+            // StdInput is the only variant when no publisher features are enabled
+            #[allow(unreachable_patterns)]
+            _ => panic!("Not expecting any other type other than StdInput")
         };
 
     }
@@ -64,4 +205,447 @@ mod tests {
         };
 
     }
+
+    #[cfg(feature = "azure_storage_queues")]
+    #[test]
+    fn test_rejects_azure_number_of_messages_out_of_range(){
+        let mut json_v = json!(
+            {"publisher_type": "AzureStorageQueue", "storage_account": "account", "queue_name": "test"}
+        );
+        for (number_of_messages, valid) in [(0, false), (1, true), (32, true), (33, false)] {
+            json_v["number_of_messages"] = json!(number_of_messages);
+            let publisher: PublisherType = serde_json::from_value(json_v.clone()).unwrap();
+            assert_eq!(publisher.validate().is_ok(), valid, "number_of_messages {}", number_of_messages);
+        }
+    }
+
+    #[cfg(feature = "zmq")]
+    #[test]
+    fn test_load_zmq_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Zmq",
+                "tcp_uri": "tcp://127.0.0.1:5556",
+                "topic": "preftopic",
+            }
+        );
+        let zmq_publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _zmq: Zmq = match zmq_publisher {
+            PublisherType::Zmq(v) => v,
+            _ => panic!("Not expecting any other type other than Zmq")
+        };
+
+    }
+
+    #[cfg(feature = "kafka")]
+    #[test]
+    fn test_load_kafka_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Kafka",
+                "bootstrap_servers": "127.0.0.1:9092",
+                "group_id": "prefect-event-router",
+                "topics": ["events"],
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Kafka = match publisher {
+            PublisherType::Kafka(v) => v,
+            _ => panic!("Not expecting any other type other than Kafka")
+        };
+
+    }
+
+    #[cfg(feature = "nats")]
+    #[test]
+    fn test_load_nats_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Nats",
+                "server_url": "nats://127.0.0.1:4222",
+                "subject": "events.>",
+                "jetstream": {"stream": "EVENTS", "durable_name": "prefect-event-router"},
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Nats = match publisher {
+            PublisherType::Nats(v) => v,
+            _ => panic!("Not expecting any other type other than Nats")
+        };
+
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn test_load_mqtt_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Mqtt",
+                "host": "127.0.0.1",
+                "port": 1883,
+                "client_id": "router-1",
+                "topics": ["sensors/#"],
+                "qos": 1,
+                "routes": [{"match": {"topic": "sensors/*"}, "flow_name": "Sensor Flow", "deployment_name": "sensors"}],
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        assert!(publisher.validate().is_ok());
+        let _pub_config: Mqtt = match publisher {
+            PublisherType::Mqtt(v) => v,
+            _ => panic!("Not expecting any other type other than Mqtt")
+        };
+
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn test_rejects_mqtt_persistent_session_without_client_id(){
+        let mut json_v = json!(
+            {"publisher_type": "Mqtt", "host": "127.0.0.1", "port": 1883, "topics": ["sensors/#"]}
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v.clone()).unwrap();
+        assert!(publisher.validate().is_err());
+        json_v["clean_session"] = json!(true);
+        let publisher: PublisherType = serde_json::from_value(json_v).unwrap();
+        assert!(publisher.validate().is_ok());
+    }
+
+    #[cfg(feature = "redis_streams")]
+    #[test]
+    fn test_load_redis_stream_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "RedisStream",
+                "url": "redis://127.0.0.1:6379",
+                "streams": ["events"],
+                "group": "prefect-event-router",
+                "consumer": "router-1",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: RedisStream = match publisher {
+            PublisherType::RedisStream(v) => v,
+            _ => panic!("Not expecting any other type other than RedisStream")
+        };
+
+    }
+
+    #[cfg(feature = "amqp")]
+    #[test]
+    fn test_load_amqp_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Amqp",
+                "uri": "amqp://127.0.0.1:5672/%2f",
+                "queue": "events",
+                "prefetch_count": 10,
+                "dead_letter_exchange": "events-dlx",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Amqp = match publisher {
+            PublisherType::Amqp(v) => v,
+            _ => panic!("Not expecting any other type other than Amqp")
+        };
+
+    }
+
+    #[cfg(feature = "sqs")]
+    #[test]
+    fn test_load_sqs_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Sqs",
+                "queue_url": "https://sqs.eu-west-1.amazonaws.com/123456789012/events",
+                "batch_size": 10,
+                "visibility_timeout": 60,
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Sqs = match publisher {
+            PublisherType::Sqs(v) => v,
+            _ => panic!("Not expecting any other type other than Sqs")
+        };
+
+    }
+
+    #[cfg(feature = "gcp_pubsub")]
+    #[test]
+    fn test_load_gcp_pubsub_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "GcpPubSub",
+                "project_id": "my-project",
+                "subscription": "events-router",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: GcpPubSub = match publisher {
+            PublisherType::GcpPubSub(v) => v,
+            _ => panic!("Not expecting any other type other than GcpPubSub")
+        };
+
+    }
+
+    #[cfg(feature = "azure_service_bus")]
+    #[test]
+    fn test_load_azure_service_bus_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "AzureServiceBus",
+                "namespace": "my-namespace.servicebus.windows.net",
+                "topic_name": "events",
+                "subscription_name": "router",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: AzureServiceBus = match publisher {
+            PublisherType::AzureServiceBus(v) => v,
+            _ => panic!("Not expecting any other type other than AzureServiceBus")
+        };
+
+    }
+
+    #[cfg(feature = "azure_event_hubs")]
+    #[test]
+    fn test_load_azure_event_hub_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "AzureEventHub",
+                "namespace": "my-namespace.servicebus.windows.net",
+                "event_hub_name": "telemetry",
+                "checkpoint_store": {"store": "File", "path": "checkpoints.json"},
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: AzureEventHub = match publisher {
+            PublisherType::AzureEventHub(v) => v,
+            _ => panic!("Not expecting any other type other than AzureEventHub")
+        };
+
+    }
+
+    #[cfg(feature = "http_webhook")]
+    #[test]
+    fn test_load_http_webhook_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "HttpWebhook",
+                "bind_address": "0.0.0.0:8080",
+                "path": "/events",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: HttpWebhook = match publisher {
+            PublisherType::HttpWebhook(v) => v,
+            _ => panic!("Not expecting any other type other than HttpWebhook")
+        };
+
+    }
+
+    #[cfg(feature = "git_webhook")]
+    #[test]
+    fn test_load_git_webhook_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "GitWebhook",
+                "bind_address": "0.0.0.0:8080",
+                "secret": "s3cret",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: GitWebhook = match publisher {
+            PublisherType::GitWebhook(v) => v,
+            _ => panic!("Not expecting any other type other than GitWebhook")
+        };
+
+    }
+
+    #[cfg(feature = "git_webhook")]
+    #[test]
+    fn test_rejects_git_webhook_without_secret(){
+        let json_v = json!({"publisher_type": "GitWebhook", "bind_address": "0.0.0.0:8080", "secret": ""});
+        let publisher: PublisherType = serde_json::from_value(json_v).unwrap();
+        assert!(publisher.validate().is_err());
+    }
+
+    #[cfg(feature = "file_drop")]
+    #[test]
+    fn test_load_file_drop_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "FileDrop",
+                "directory": "/mnt/share/events",
+                "ndjson": true,
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: FileDrop = match publisher {
+            PublisherType::FileDrop(v) => v,
+            _ => panic!("Not expecting any other type other than FileDrop")
+        };
+
+    }
+
+    #[cfg(feature = "socket")]
+    #[test]
+    fn test_load_socket_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Socket",
+                "unix_path": "/tmp/router.sock",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Socket = match publisher {
+            PublisherType::Socket(v) => v,
+            _ => panic!("Not expecting any other type other than Socket")
+        };
+
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn test_load_postgres_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Postgres",
+                "connection_string": "postgres://router@localhost/app",
+                "outbox": {"table": "outbox"},
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Postgres = match publisher {
+            PublisherType::Postgres(v) => v,
+            _ => panic!("Not expecting any other type other than Postgres")
+        };
+
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_load_sqlite_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Sqlite",
+                "path": "/var/lib/router/events.db",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Sqlite = match publisher {
+            PublisherType::Sqlite(v) => v,
+            _ => panic!("Not expecting any other type other than Sqlite")
+        };
+
+    }
+
+    #[cfg(feature = "schedule")]
+    #[test]
+    fn test_load_schedule_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Schedule",
+                "interval_seconds": 300,
+                "message": {"flow_name": "Test Flow", "deployment_name": "test"},
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Schedule = match publisher {
+            PublisherType::Schedule(v) => v,
+            _ => panic!("Not expecting any other type other than Schedule")
+        };
+
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_load_websocket_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "WebSocket",
+                "url": "wss://feed.example.com/stream",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: WebSocket = match publisher {
+            PublisherType::WebSocket(v) => v,
+            _ => panic!("Not expecting any other type other than WebSocket")
+        };
+
+    }
+
+    #[cfg(feature = "sse")]
+    #[test]
+    fn test_load_sse_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "Sse",
+                "url": "https://feed.example.com/events",
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: Sse = match publisher {
+            PublisherType::Sse(v) => v,
+            _ => panic!("Not expecting any other type other than Sse")
+        };
+
+    }
+
+    #[cfg(feature = "prefect_events")]
+    #[test]
+    fn test_load_prefect_events_publisher_type(){
+        let json_v = json!(
+            {
+                "publisher_type": "PrefectEvents",
+                "filter": {"event": {"name": ["prefect.flow-run.Completed"]}},
+            }
+        );
+        let publisher: PublisherType = serde_json::from_value(json_v).expect(
+            "Unable to parse json as a valid publisher type"
+        );
+        let _pub_config: PrefectEvents = match publisher {
+            PublisherType::PrefectEvents(v) => v,
+            _ => panic!("Not expecting any other type other than PrefectEvents")
+        };
+
+    }
 }
@@ -0,0 +1,37 @@
+//! Setup shared by the Azure publishers
+use azure_identity::DefaultAzureCredential;
+#[cfg(any(feature = "azure_service_bus", feature = "azure_event_hubs"))]
+use fe2o3_amqp_types::primitives::SimpleValue;
+
+/// The credential used when no key, SAS or connection string is configured. It looks for
+/// the environment variables, the managed identity of the VM and the Azure CLI login
+pub(crate) fn default_credential() -> DefaultAzureCredential {
+    DefaultAzureCredential::default()
+}
+
+/// Application properties that can be matched by routes. Other types are left out
+#[cfg(any(feature = "azure_service_bus", feature = "azure_event_hubs"))]
+pub(crate) fn property_str(value: &SimpleValue) -> Option<String> {
+    match value {
+        SimpleValue::String(s) => Some(s.clone()),
+        SimpleValue::Symbol(s) => Some(s.0.clone()),
+        SimpleValue::Bool(b) => Some(b.to_string()),
+        SimpleValue::Int(i) => Some(i.to_string()),
+        SimpleValue::Long(l) => Some(l.to_string()),
+        _ => None
+    }
+}
+
+#[cfg(all(test, any(feature = "azure_service_bus", feature = "azure_event_hubs")))]
+mod tests {
+    use super::property_str;
+    use fe2o3_amqp_types::primitives::{SimpleValue, Symbol};
+
+    #[test]
+    fn test_property_str() {
+        assert_eq!(property_str(&SimpleValue::String("sensor".to_string())), Some("sensor".to_string()));
+        assert_eq!(property_str(&SimpleValue::Symbol(Symbol::from("sensor"))), Some("sensor".to_string()));
+        assert_eq!(property_str(&SimpleValue::Long(42)), Some("42".to_string()));
+        assert_eq!(property_str(&SimpleValue::Null), None);
+    }
+}
@@ -0,0 +1,482 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use crate::publishers::azure::{default_credential, property_str};
+use crate::routing::Route;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinSet};
+use azeventhubs::consumer::{
+    EventHubConsumerClient, EventHubConsumerClientOptions, EventPosition, ReadEventOptions
+};
+use azeventhubs::ReceivedEventData;
+use azure_core::error::ErrorKind;
+use azure_core::StatusCode;
+use azure_storage::prelude::*;
+use azure_storage_blobs::prelude::*;
+
+const DEFAULT_CONSUMER_GROUP: &str = "$Default";
+const RECONNECT_INTERVAL_MS: u64 = 1000;
+// events read ahead of the trigger, per partition
+const CHANNEL_CAPACITY: usize = 100;
+const DEFAULT_CHECKPOINT_INTERVAL_MS: u64 = 5000;
+const DEFAULT_RETRY_DELAY_MS: u64 = 5000;
+
+/// Where the sequence number of the last triggered event of each partition is kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "store")]
+pub enum CheckpointStore {
+    File {path: String},
+    Blob {storage_account: String, container: String}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AzureEventHub {
+    /// Fully qualified namespace, eg. `my-namespace.servicebus.windows.net`
+    pub namespace: String,
+    pub event_hub_name: String,
+    /// Defaults to `$Default`
+    pub consumer_group: Option<String>,
+    pub checkpoint_store: CheckpointStore,
+    /// Partitions without a checkpoint start from the earliest retained event instead of
+    /// only new ones
+    pub start_from_earliest: Option<bool>,
+    /// Minimum time between writes of the checkpoints
+    pub checkpoint_interval_ms: Option<u64>,
+    /// Wait before a partition is read again from an event whose trigger failed
+    pub retry_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(skip_serializing, skip_deserializing)]
+    checkpointer: Option<Checkpointer>,
+    #[serde(skip_serializing, skip_deserializing)]
+    checkpoints: HashMap<String, i64>,
+    /// Whether the checkpoints changed since they were last written
+    #[serde(skip_serializing, skip_deserializing)]
+    dirty: bool,
+    #[serde(skip_serializing, skip_deserializing)]
+    last_saved: Option<Instant>,
+    #[serde(skip_serializing, skip_deserializing)]
+    sender: Option<mpsc::Sender<EventHubMsg>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    events: Option<mpsc::Receiver<EventHubMsg>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    readers: JoinSet<()>,
+    /// The generation and task of the current reader of each partition
+    #[serde(skip_serializing, skip_deserializing)]
+    partitions: HashMap<String, (u64, AbortHandle)>
+}
+// the partition readers cannot be shared between threads so a clone only carries the config
+impl Clone for AzureEventHub {
+    fn clone(&self) -> Self {
+        Self {
+            namespace: self.namespace.clone(),
+            event_hub_name: self.event_hub_name.clone(),
+            consumer_group: self.consumer_group.clone(),
+            checkpoint_store: self.checkpoint_store.clone(),
+            start_from_earliest: self.start_from_earliest,
+            checkpoint_interval_ms: self.checkpoint_interval_ms,
+            retry_delay_ms: self.retry_delay_ms,
+            routes: self.routes.clone(),
+            checkpointer: None,
+            checkpoints: HashMap::new(),
+            dirty: false,
+            last_saved: None,
+            sender: None,
+            events: None,
+            readers: JoinSet::new(),
+            partitions: HashMap::new()
+        }
+    }
+}
+impl fmt::Debug for AzureEventHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AzureEventHub")
+            .field("namespace", &self.namespace)
+            .field("event_hub_name", &self.event_hub_name)
+            .field("consumer_group", &self.consumer_group)
+            .field("checkpoint_store", &self.checkpoint_store)
+            .field("start_from_earliest", &self.start_from_earliest)
+            .field("checkpoint_interval_ms", &self.checkpoint_interval_ms)
+            .field("retry_delay_ms", &self.retry_delay_ms)
+            .field("routes", &self.routes)
+            .field("connected", &self.events.is_some())
+            .finish()
+    }
+}
+
+pub struct EventHubMsg {
+    partition_id: String,
+    /// Generation of the partition reader that read the event
+    generation: u64,
+    sequence_number: i64,
+    msg: String,
+    attributes: HashMap<String, String>
+}
+impl EventHubMsg {
+    fn new(partition_id: &str, generation: u64, event: &ReceivedEventData) -> Self {
+        let msg = match event.body() {
+            Ok(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            Err(_) => String::new()
+        };
+        let mut attributes = HashMap::new();
+        if let Some(properties) = event.properties() {
+            for (key, value) in properties.0.iter() {
+                if let Some(value) = property_str(value) {
+                    attributes.insert(key.clone(), value);
+                }
+            }
+        }
+        attributes.insert("partition_id".to_string(), partition_id.to_string());
+        Self {
+            partition_id: partition_id.to_string(),
+            generation,
+            sequence_number: event.sequence_number(),
+            msg,
+            attributes
+        }
+    }
+}
+impl RawMessage for EventHubMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        self.attributes.clone()
+    }
+}
+
+enum Checkpointer {
+    File(String),
+    Blob(BlobClient)
+}
+impl Checkpointer {
+    async fn load(&self) -> Result<HashMap<String, i64>, String> {
+        let content = match self {
+            Checkpointer::File(path) => match tokio::fs::read(path).await {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+                Err(e) => return Err(e.to_string())
+            },
+            Checkpointer::Blob(blob) => match blob.get_content().await {
+                Ok(content) => content,
+                Err(e) if matches!(e.kind(), ErrorKind::HttpResponse {status: StatusCode::NotFound, ..}) => {
+                    return Ok(HashMap::new())
+                },
+                Err(e) => return Err(e.to_string())
+            }
+        };
+        serde_json::from_slice(&content).map_err(|e| e.to_string())
+    }
+    async fn save(&self, checkpoints: &HashMap<String, i64>) -> Result<(), String> {
+        let content = serde_json::to_vec(checkpoints).map_err(|e| e.to_string())?;
+        match self {
+            Checkpointer::File(path) => {
+                // written aside and renamed so a crash never leaves a truncated file
+                let tmp_path = format!("{}.tmp", path);
+                tokio::fs::write(&tmp_path, content).await.map_err(|e| e.to_string())?;
+                tokio::fs::rename(&tmp_path, path).await.map_err(|e| e.to_string())
+            },
+            Checkpointer::Blob(blob) => {
+                blob.put_block_blob(content)
+                    .content_type("application/json")
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+struct PartitionReader {
+    namespace: String,
+    event_hub_name: String,
+    consumer_group: String,
+    partition_id: String,
+    generation: u64
+}
+impl PartitionReader {
+    async fn client(&self) -> Result<EventHubConsumerClient<azeventhubs::BasicRetryPolicy>, azure_core::Error> {
+        EventHubConsumerClient::new_from_credential(
+            self.consumer_group.clone(),
+            self.namespace.clone(),
+            self.event_hub_name.clone(),
+            default_credential(),
+            EventHubConsumerClientOptions::default()
+        ).await
+    }
+    /// Forwards the events of the partition in order, reconnecting from the last event
+    /// read on errors, until the publisher is dropped
+    async fn run(self, mut position: EventPosition, sender: mpsc::Sender<EventHubMsg>) {
+        loop {
+            match self.client().await {
+                Ok(mut client) => {
+                    let stream = client.read_events_from_partition(
+                        &self.partition_id, position.clone(), ReadEventOptions::default()
+                    ).await;
+                    match stream {
+                        Ok(mut stream) => {
+                            while let Some(event) = stream.next().await {
+                                let event = match event {
+                                    Ok(event) => event,
+                                    Err(e) => {
+                                        println!("Event Hub partition {}: Got consumer error {:?}", &self.partition_id, e);
+                                        break
+                                    }
+                                };
+                                position = EventPosition::from_sequence_number(event.sequence_number(), false);
+                                if sender.send(EventHubMsg::new(&self.partition_id, self.generation, &event)).await.is_err() {
+                                    return
+                                }
+                            }
+                        },
+                        Err(e) => println!("Event Hub partition {}: Unable to read events: {:?}", &self.partition_id, e)
+                    }
+                },
+                Err(e) => println!("Event Hub partition {}: Unable to connect: {:?}", &self.partition_id, e)
+            }
+            tokio::time::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS)).await;
+        }
+    }
+}
+
+impl AzureEventHub {
+    fn consumer_group(&self) -> String {
+        self.consumer_group.clone().unwrap_or(DEFAULT_CONSUMER_GROUP.to_string())
+    }
+    fn checkpointer(&self) -> Checkpointer {
+        match &self.checkpoint_store {
+            CheckpointStore::File {path} => Checkpointer::File(path.clone()),
+            CheckpointStore::Blob {storage_account, container} => {
+                let credential = Arc::new(default_credential());
+                let storage_credentials = StorageCredentials::token_credential(credential);
+                let blob_name = format!(
+                    "{}/{}/{}/checkpoints.json", &self.namespace, &self.event_hub_name, self.consumer_group()
+                );
+                Checkpointer::Blob(
+                    BlobServiceClient::new(storage_account, storage_credentials)
+                        .container_client(container)
+                        .blob_client(blob_name)
+                )
+            }
+        }
+    }
+    fn start_position(&self, partition_id: &str) -> EventPosition {
+        match self.checkpoints.get(partition_id) {
+            Some(sequence_number) => EventPosition::from_sequence_number(*sequence_number, false),
+            None if self.start_from_earliest.unwrap_or(false) => EventPosition::earliest(),
+            None => EventPosition::latest()
+        }
+    }
+    /// Starts a new reader of the partition, replacing the previous one. Events the old
+    /// reader had already queued are dropped by their generation
+    fn read_partition(&mut self, partition_id: String, position: EventPosition) {
+        let generation = match self.partitions.remove(&partition_id) {
+            Some((generation, reader)) => {
+                reader.abort();
+                generation + 1
+            },
+            None => 0
+        };
+        let reader = PartitionReader {
+            namespace: self.namespace.clone(),
+            event_hub_name: self.event_hub_name.clone(),
+            consumer_group: self.consumer_group(),
+            partition_id: partition_id.clone(),
+            generation
+        };
+        let sender = self.sender.clone().expect(
+            "Cannot read a partition without the event channel being initialised"
+        );
+        let handle = self.readers.spawn(reader.run(position, sender));
+        self.partitions.insert(partition_id, (generation, handle));
+    }
+    /// Loads the checkpoints and starts a reader for every partition
+    async fn start(&mut self) -> Result<(), String> {
+        if self.checkpointer.is_none() {
+            let checkpointer = self.checkpointer();
+            self.checkpoints = checkpointer.load().await.map_err(|e| format!("Unable to load checkpoints: {}", e))?;
+            self.checkpointer = Some(checkpointer);
+        }
+        let mut client = EventHubConsumerClient::new_from_credential(
+            self.consumer_group(),
+            self.namespace.clone(),
+            self.event_hub_name.clone(),
+            default_credential(),
+            EventHubConsumerClientOptions::default()
+        ).await.map_err(|e| format!("Unable to connect: {:?}", e))?;
+        let partition_ids = client.get_partition_ids().await;
+        if let Err(e) = client.close().await {
+            println!("{}: Failed to close the client: {:?}", self.repr(), e)
+        }
+        let partition_ids = partition_ids.map_err(|e| format!("Unable to list partitions: {:?}", e))?;
+        let (sender, events) = mpsc::channel(CHANNEL_CAPACITY * partition_ids.len().max(1));
+        self.sender = Some(sender);
+        self.events = Some(events);
+        for partition_id in partition_ids {
+            let position = self.start_position(&partition_id);
+            self.read_partition(partition_id, position);
+        }
+        Ok(())
+    }
+    fn is_current(&self, message: &EventHubMsg) -> bool {
+        self.partitions.get(&message.partition_id)
+            .is_some_and(|(generation, _)| *generation == message.generation)
+    }
+    /// Writes the checkpoints if they changed, at most once per checkpoint interval
+    async fn save_checkpoints(&mut self) {
+        let interval = Duration::from_millis(self.checkpoint_interval_ms.unwrap_or(DEFAULT_CHECKPOINT_INTERVAL_MS));
+        let due = match self.last_saved {
+            Some(last) => last.elapsed() >= interval,
+            None => true
+        };
+        if !self.dirty || !due {
+            return
+        }
+        let saved = self.checkpointer.as_ref().expect(
+            "Cannot save checkpoints when the checkpoint store is not initialised"
+        ).save(&self.checkpoints).await;
+        match saved {
+            Ok(()) => self.dirty = false,
+            Err(e) => println!("{}: Failed to save checkpoints: {}", self.repr(), e)
+        }
+        self.last_saved = Some(Instant::now());
+    }
+}
+
+#[async_trait]
+impl Publisher for AzureEventHub {
+    type PubMessage = EventHubMsg;
+
+    fn repr(&self) -> String {
+        format!("{}/{}/{}", &self.namespace, &self.event_hub_name, self.consumer_group())
+    }
+    async fn init(&mut self) {
+        // a transient Azure error at startup is retried by next_message
+        if let Err(e) = self.start().await {
+            println!("{}: Failed to start: {}", self.repr(), e)
+        }
+    }
+    async fn next_message(&mut self) -> Option<EventHubMsg> {
+        let events = match self.events.as_mut() {
+            Some(events) => events,
+            None => {
+                tokio::time::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS)).await;
+                if let Err(e) = self.start().await {
+                    println!("{}: Failed to start: {}", self.repr(), e)
+                }
+                return None
+            }
+        };
+        // a quiet partition still gets the checkpoints of its last events written
+        let interval = Duration::from_millis(self.checkpoint_interval_ms.unwrap_or(DEFAULT_CHECKPOINT_INTERVAL_MS));
+        let received = tokio::time::timeout(interval, events.recv()).await;
+        self.save_checkpoints().await;
+        match received {
+            Ok(Some(message)) if self.is_current(&message) => Some(message),
+            _ => None
+        }
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        self.checkpoints.insert(message.partition_id.clone(), message.sequence_number);
+        self.dirty = true;
+        self.save_checkpoints().await
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, _error: &Error) {
+        // reads the partition again from the failed event so that later events are neither
+        // triggered nor checkpointed before it
+        tokio::time::sleep(Duration::from_millis(self.retry_delay_ms.unwrap_or(DEFAULT_RETRY_DELAY_MS))).await;
+        let position = EventPosition::from_sequence_number(message.sequence_number, true);
+        self.read_partition(message.partition_id, position);
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        // reading it again would not make the event valid
+        self.task_done(message).await
+    }
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::Publisher;
+
+    use super::{AzureEventHub, Checkpointer, EventHubMsg};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_task_done_checkpoints_to_file() {
+        let suffix: u32 = rand::random();
+        let path = std::env::temp_dir().join(format!("router-checkpoints-{}.json", suffix));
+        let path = path.to_str().unwrap().to_string();
+        let mut event_hub: AzureEventHub = serde_json::from_value(json!(
+            {
+                "namespace": "ns.servicebus.windows.net",
+                "event_hub_name": "telemetry",
+                "checkpoint_store": {"store": "File", "path": &path}
+            }
+        )).unwrap();
+        let checkpointer = event_hub.checkpointer();
+        assert!(checkpointer.load().await.unwrap().is_empty());
+        event_hub.checkpointer = Some(checkpointer);
+
+        let msg = |sequence_number| EventHubMsg {
+            partition_id: "1".to_string(), generation: 0, sequence_number, msg: String::new(), attributes: HashMap::new()
+        };
+        event_hub.task_done(msg(42)).await;
+        let saved = Checkpointer::File(path.clone()).load().await.unwrap();
+        assert_eq!(saved["1"], 42);
+        // writes are throttled to the checkpoint interval
+        event_hub.task_done(msg(43)).await;
+        let saved = Checkpointer::File(path.clone()).load().await.unwrap();
+        assert_eq!(saved["1"], 42);
+        event_hub.last_saved = None;
+        event_hub.save_checkpoints().await;
+        let saved = Checkpointer::File(path.clone()).load().await.unwrap();
+        assert_eq!(saved["1"], 43);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_events_of_replaced_reader_are_dropped() {
+        let mut event_hub: AzureEventHub = serde_json::from_value(json!(
+            {
+                "namespace": "ns.servicebus.windows.net",
+                "event_hub_name": "telemetry",
+                "checkpoint_store": {"store": "File", "path": "unused.json"}
+            }
+        )).unwrap();
+        let reader = event_hub.readers.spawn(std::future::pending());
+        event_hub.partitions.insert("1".to_string(), (1, reader));
+        let msg = |generation| EventHubMsg {
+            partition_id: "1".to_string(), generation, sequence_number: 7, msg: String::new(), attributes: HashMap::new()
+        };
+        assert!(event_hub.is_current(&msg(1)));
+        assert!(!event_hub.is_current(&msg(0)));
+    }
+
+    #[tokio::test]
+    async fn test_retries_start_instead_of_panicking() {
+        let suffix: u32 = rand::random();
+        let path = std::env::temp_dir().join(format!("router-checkpoints-{}.json", suffix));
+        std::fs::write(&path, "not json").unwrap();
+        let mut event_hub: AzureEventHub = serde_json::from_value(json!(
+            {
+                "namespace": "ns.servicebus.windows.net",
+                "event_hub_name": "telemetry",
+                "checkpoint_store": {"store": "File", "path": path.to_str().unwrap()}
+            }
+        )).unwrap();
+        event_hub.init().await;
+        assert!(event_hub.events.is_none());
+        assert!(event_hub.next_message().await.is_none());
+        assert!(event_hub.checkpointer.is_none(), "Checkpoints should be loaded again on the next attempt");
+        std::fs::remove_file(path).unwrap();
+    }
+}
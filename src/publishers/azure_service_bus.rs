@@ -0,0 +1,291 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use crate::publishers::azure::{default_credential, property_str};
+use crate::routing::Route;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+use azservicebus::core::BasicRetryPolicy;
+use azservicebus::receiver::DeadLetterOptions;
+use azservicebus::{
+    ServiceBusClient, ServiceBusClientOptions, ServiceBusReceivedMessage, ServiceBusReceiver,
+    ServiceBusReceiverOptions
+};
+
+const DEFAULT_MAX_MESSAGES: u32 = 10;
+const DEFAULT_PREFETCH_COUNT: u32 = 0;
+const DEFAULT_MAX_WAIT_SECONDS: u64 = 30;
+const RECONNECT_INTERVAL_MS: u64 = 1000;
+
+#[derive(Serialize, Deserialize)]
+pub struct AzureServiceBus {
+    /// Fully qualified namespace, eg. `my-namespace.servicebus.windows.net`
+    pub namespace: String,
+    /// Set either the queue or the topic and subscription to receive from
+    pub queue_name: Option<String>,
+    pub topic_name: Option<String>,
+    pub subscription_name: Option<String>,
+    pub max_messages: Option<u32>,
+    /// How long a receive call waits for the first message
+    pub max_wait_seconds: Option<u64>,
+    /// Messages the link receives ahead of time. Their locks run out while they wait so
+    /// this defaults to 0
+    pub prefetch_count: Option<u32>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(skip_serializing, skip_deserializing)]
+    connection: Option<Box<(ServiceBusClient<BasicRetryPolicy>, ServiceBusReceiver)>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    messages: Vec<AzureServiceBusMsg>,
+    /// When the locks of the received messages were taken and how long they last
+    #[serde(skip_serializing, skip_deserializing)]
+    locked: Option<(Instant, Duration)>
+}
+// the connection cannot be shared between threads so a clone only carries the config
+impl Clone for AzureServiceBus {
+    fn clone(&self) -> Self {
+        Self {
+            namespace: self.namespace.clone(),
+            queue_name: self.queue_name.clone(),
+            topic_name: self.topic_name.clone(),
+            subscription_name: self.subscription_name.clone(),
+            max_messages: self.max_messages,
+            max_wait_seconds: self.max_wait_seconds,
+            prefetch_count: self.prefetch_count,
+            routes: self.routes.clone(),
+            connection: None,
+            messages: Vec::new(),
+            locked: None
+        }
+    }
+}
+impl fmt::Debug for AzureServiceBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AzureServiceBus")
+            .field("namespace", &self.namespace)
+            .field("queue_name", &self.queue_name)
+            .field("topic_name", &self.topic_name)
+            .field("subscription_name", &self.subscription_name)
+            .field("max_messages", &self.max_messages)
+            .field("max_wait_seconds", &self.max_wait_seconds)
+            .field("prefetch_count", &self.prefetch_count)
+            .field("routes", &self.routes)
+            .field("connected", &self.connection.is_some())
+            .finish()
+    }
+}
+
+pub struct AzureServiceBusMsg {
+    msg: String,
+    attributes: HashMap<String, String>,
+    message: ServiceBusReceivedMessage
+}
+impl AzureServiceBusMsg {
+    fn new(message: ServiceBusReceivedMessage) -> Self {
+        let msg = match message.body() {
+            Ok(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            Err(_) => String::new()
+        };
+        let mut attributes = HashMap::new();
+        if let Some(properties) = message.application_properties() {
+            for (key, value) in properties.0.iter() {
+                if let Some(value) = property_str(value) {
+                    attributes.insert(key.clone(), value);
+                }
+            }
+        }
+        if let Some(subject) = message.subject() {
+            attributes.insert("subject".to_string(), subject.to_string());
+        }
+        Self {msg, attributes, message}
+    }
+}
+impl RawMessage for AzureServiceBusMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        self.attributes.clone()
+    }
+}
+
+/// How long from now the lock of the first message lasts
+fn lock_duration(messages: &[AzureServiceBusMsg]) -> Option<Duration> {
+    let locked_until = messages.first()?.message.locked_until()?;
+    Some(azure_core::date::diff(locked_until, SystemTime::now().into()))
+}
+
+enum Entity {
+    Queue(String),
+    Subscription(String, String)
+}
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entity::Queue(queue) => write!(f, "{}", queue),
+            Entity::Subscription(topic, subscription) => write!(f, "{}/Subscriptions/{}", topic, subscription)
+        }
+    }
+}
+
+// takes owned config so that no reference to the (non Sync) publisher is held across awaits
+async fn receive_from(
+    namespace: String, entity: Entity, options: ServiceBusReceiverOptions
+) -> Result<(ServiceBusClient<BasicRetryPolicy>, ServiceBusReceiver), azure_core::Error> {
+    let mut client = ServiceBusClient::new_from_token_credential(
+        namespace, default_credential(), ServiceBusClientOptions::default()
+    ).await?;
+    let receiver = match entity {
+        Entity::Queue(queue) => client.create_receiver_for_queue(queue, options).await?,
+        Entity::Subscription(topic, subscription) => {
+            client.create_receiver_for_subscription(topic, subscription, options).await?
+        }
+    };
+    Ok((client, receiver))
+}
+
+impl AzureServiceBus {
+    fn entity(&self) -> Result<Entity, String> {
+        match (&self.queue_name, &self.topic_name, &self.subscription_name) {
+            (Some(queue), None, None) => Ok(Entity::Queue(queue.clone())),
+            (None, Some(topic), Some(subscription)) => Ok(Entity::Subscription(topic.clone(), subscription.clone())),
+            _ => Err("AzureServiceBus needs either queue_name or both topic_name and subscription_name".to_string())
+        }
+    }
+    async fn connect(&mut self) {
+        // peek-lock is the default receive mode
+        let options = ServiceBusReceiverOptions {
+            prefetch_count: self.prefetch_count.unwrap_or(DEFAULT_PREFETCH_COUNT),
+            ..Default::default()
+        };
+        let entity = self.entity().expect("The entity is validated in init");
+        let received = receive_from(self.namespace.clone(), entity, options).await;
+        self.connection = match received {
+            Ok(connection) => Some(Box::new(connection)),
+            Err(e) => {
+                println!("{}: Unable to receive from entity: {:?}", self.repr(), e);
+                None
+            }
+        }
+    }
+    /// Messages of a batch wait for the triggers of the ones before them, so once half of
+    /// the lock duration has passed the locks of the remaining ones are renewed
+    async fn renew_locks(&mut self) {
+        let due = self.locked.is_some_and(|(since, duration)| since.elapsed() >= duration / 2);
+        if !due || self.messages.is_empty() {
+            return
+        }
+        let repr = self.repr();
+        let Some(connection) = self.connection.as_mut() else {
+            return
+        };
+        for message in self.messages.iter_mut() {
+            if let Err(e) = connection.1.renew_message_lock(&mut message.message).await {
+                println!("{}: Failed to renew message lock: {:?}", repr, e)
+            }
+        }
+        self.locked = lock_duration(&self.messages).map(|duration| (Instant::now(), duration));
+    }
+    fn receiver(&mut self) -> &mut ServiceBusReceiver {
+        &mut self.connection.as_mut().expect(
+            "Cannot settle a message when the receiver is not initialised"
+        ).1
+    }
+}
+
+#[async_trait]
+impl Publisher for AzureServiceBus {
+    type PubMessage = AzureServiceBusMsg;
+
+    fn repr(&self) -> String {
+        match self.entity() {
+            Ok(entity) => format!("{}/{}", &self.namespace, entity),
+            Err(_) => self.namespace.clone()
+        }
+    }
+    async fn init(&mut self) {
+        if let Err(e) = self.entity() {
+            panic!("{}: {}", self.repr(), e)
+        }
+        self.connect().await
+    }
+    async fn next_message(&mut self) -> Option<AzureServiceBusMsg> {
+        if !self.messages.is_empty() {
+            self.renew_locks().await;
+            return self.messages.pop()
+        }
+        let max_messages = self.max_messages.unwrap_or(DEFAULT_MAX_MESSAGES);
+        let max_wait = Duration::from_secs(self.max_wait_seconds.unwrap_or(DEFAULT_MAX_WAIT_SECONDS));
+        let received = match self.connection.as_mut() {
+            Some(connection) => connection.1.receive_messages_with_max_wait_time(max_messages, max_wait).await,
+            None => {
+                tokio::time::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS)).await;
+                println!("{}: Reconnecting", self.repr());
+                self.connect().await;
+                return None
+            }
+        };
+        match received {
+            // popped from the back so reverse to keep the received order
+            Ok(messages) => {
+                self.messages = messages.into_iter().rev().map(AzureServiceBusMsg::new).collect();
+                self.locked = lock_duration(&self.messages).map(|duration| (Instant::now(), duration));
+            },
+            Err(e) => {
+                println!("{}: Failed to receive messages: {:?}", self.repr(), e);
+                self.connection = None;
+            }
+        }
+        self.messages.pop()
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        if let Err(e) = self.receiver().complete_message(&message.message).await {
+            println!("{}: Failed to complete message: {:?}", self.repr(), e)
+        }
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, _error: &Error) {
+        // releases the lock so the message is redelivered, up to the entity's max delivery count
+        if let Err(e) = self.receiver().abandon_message(&message.message, None).await {
+            println!("{}: Failed to abandon message: {:?}", self.repr(), e)
+        }
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, error: &Error) {
+        let options = DeadLetterOptions {
+            dead_letter_reason: Some("InvalidMessage".to_string()),
+            dead_letter_error_description: Some(error.to_string()),
+            properties_to_modify: None
+        };
+        if let Err(e) = self.receiver().dead_letter_message(&message.message, options).await {
+            println!("{}: Failed to dead-letter message: {:?}", self.repr(), e)
+        }
+    }
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::Publisher;
+
+    use super::AzureServiceBus;
+    use serde_json::json;
+
+    #[test]
+    fn test_entity_of_queue_and_subscription() {
+        let queue: AzureServiceBus = serde_json::from_value(json!(
+            {"namespace": "ns.servicebus.windows.net", "queue_name": "events"}
+        )).unwrap();
+        assert_eq!(queue.entity().unwrap().to_string(), "events");
+        let subscription: AzureServiceBus = serde_json::from_value(json!(
+            {"namespace": "ns.servicebus.windows.net", "topic_name": "events", "subscription_name": "router"}
+        )).unwrap();
+        assert_eq!(subscription.entity().unwrap().to_string(), "events/Subscriptions/router");
+        let both: AzureServiceBus = serde_json::from_value(json!(
+            {"namespace": "ns.servicebus.windows.net", "queue_name": "events", "topic_name": "events"}
+        )).unwrap();
+        assert!(both.entity().is_err());
+        assert_eq!(both.repr(), "ns.servicebus.windows.net");
+    }
+}
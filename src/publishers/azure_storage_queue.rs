@@ -1,79 +1,552 @@
-use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use azure_storage_queues::prelude::*;
 use azure_storage::prelude::*;
+use azure_storage::{CloudLocation, ConnectionString};
+use azure_core::auth::Secret;
 use async_trait::async_trait;
-use std::sync::Arc;
-use azure_identity::DefaultAzureCredential;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 use azure_storage_queues::operations::Message;
+use azure_storage_queues::QueueServiceClientBuilder;
+use base64::{engine::general_purpose::STANDARD, Engine};
 
-use crate::interfaces::{Publisher, RawMessage};
+use crate::interfaces::{Error, Publisher, RawMessage};
+use crate::publishers::azure::default_credential;
+use crate::routing::Route;
 
+const EMULATOR_ADDRESS: &str = "127.0.0.1";
+const EMULATOR_QUEUE_PORT: u16 = 10001;
+const DEFAULT_VISIBILITY_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_MIN_POLL_INTERVAL_MS: u64 = 1000;
+const DEFAULT_MAX_POLL_INTERVAL_MS: u64 = 30000;
+// same default as the Azure Functions queue trigger
+const DEFAULT_MAX_DEQUEUE_COUNT: u64 = 5;
 
-impl RawMessage for Message {
+/// A received message whose visibility timeout is kept renewed until it is settled
+#[derive(Debug)]
+pub struct AzureStorageQueueMsg {
+    message: Message,
+    content: String,
+    attributes: HashMap<String, String>,
+    pop_receipt: Arc<Mutex<String>>,
+    lease: Option<(oneshot::Sender<()>, JoinHandle<()>)>
+}
+impl AzureStorageQueueMsg {
+    fn leased(queue_client: &QueueClient, message: Message, visibility_timeout: u64) -> Self {
+        let pop_receipt = Arc::new(Mutex::new(message.pop_receipt.clone()));
+        let (stop, mut stopped) = oneshot::channel();
+        let queue_client = queue_client.clone();
+        let message_id = message.message_id.clone();
+        let message_text = message.message_text.clone();
+        let current_receipt = pop_receipt.clone();
+        // dropping the message drops the sender, which also ends the lease
+        let renewal = tokio::spawn(async move {
+            let every = Duration::from_secs((visibility_timeout / 2).max(1));
+            loop {
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    _ = tokio::time::sleep(every) => {}
+                }
+                let receipt = current_receipt.lock().unwrap().clone();
+                let updated = queue_client.pop_receipt_client(PopReceipt::new(&message_id, receipt))
+                    .update(message_text.clone(), Duration::from_secs(visibility_timeout)).await;
+                match updated {
+                    Ok(response) => *current_receipt.lock().unwrap() = response.pop_receipt,
+                    Err(e) => println!(
+                        "{}: Failed to renew visibility of message {}: {:?}", queue_client.queue_name(), &message_id, e
+                    )
+                }
+            }
+        });
+        Self {
+            content: message.message_text.clone(),
+            attributes: HashMap::new(),
+            message,
+            pop_receipt,
+            lease: Some((stop, renewal))
+        }
+    }
+    /// Stops renewing the visibility timeout and returns the latest pop receipt. An update
+    /// in progress is let finish so that its receipt is not lost
+    async fn release(&mut self) -> PopReceipt {
+        if let Some((stop, renewal)) = self.lease.take() {
+            let _ = stop.send(());
+            let _ = renewal.await;
+        }
+        PopReceipt::new(&self.message.message_id, self.pop_receipt.lock().unwrap().clone())
+    }
+}
+impl RawMessage for AzureStorageQueueMsg {
     fn get_content_str(&self) -> String {
-        self.message_text.clone()
+        self.content.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        self.attributes.clone()
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AzureStorageQueue {
+    /// Can be left out when the connection_string names the account
+    #[serde(default)]
     pub storage_account: String,
     pub queue_name: String, 
+    /// eg. `DefaultEndpointsProtocol=https;AccountName=...;AccountKey=...` or
+    /// `UseDevelopmentStorage=true` for Azurite
+    pub connection_string: Option<String>,
+    pub account_key: Option<String>,
+    pub sas_token: Option<String>,
+    /// Queue service URL for other clouds or emulators, eg. `http://127.0.0.1:10001/devstoreaccount1`
+    pub endpoint: Option<String>,
+    /// Messages received per request, up to 32. Defaults to 1
+    pub number_of_messages: Option<u8>,
+    /// Seconds a received message stays invisible to other consumers. Defaults to 30
+    pub visibility_timeout: Option<u64>,
+    /// Wait after the first empty poll, doubled on each further empty poll
+    pub min_poll_interval_ms: Option<u64>,
+    /// Upper bound of the wait between polls of an empty queue
+    pub max_poll_interval_ms: Option<u64>,
+    /// Failed attempts after which a message is moved to the poison queue. Defaults to 5
+    pub max_dequeue_count: Option<u64>,
+    /// Defaults to `<queue_name>-poison`
+    pub poison_queue_name: Option<String>,
+    /// Decode message text that was base64 encoded, as the Azure SDKs and Event Grid do
+    #[serde(default)]
+    pub base64_decode: bool,
+    /// Use the `data` of an Event Grid or CloudEvents event as the message content
+    #[serde(default)]
+    pub unwrap_event_grid: bool,
+    #[serde(default)]
+    pub routes: Vec<Route>,
 
     #[serde(skip_serializing, skip_deserializing)]
     queue_client: Option<QueueClient>,
     #[serde(skip_serializing, skip_deserializing)]
-    messages: Option<Vec<Message>>
+    poison_queue_client: Option<QueueClient>,
+    #[serde(skip_serializing, skip_deserializing)]
+    poison_queue_created: bool,
+    #[serde(skip_serializing, skip_deserializing)]
+    messages: Vec<AzureStorageQueueMsg>,
+    #[serde(skip_serializing, skip_deserializing)]
+    poll_interval_ms: Option<u64>
+}
+// in flight messages belong to one thread so a clone only carries the config
+impl Clone for AzureStorageQueue {
+    fn clone(&self) -> Self {
+        Self {
+            storage_account: self.storage_account.clone(),
+            queue_name: self.queue_name.clone(),
+            connection_string: self.connection_string.clone(),
+            account_key: self.account_key.clone(),
+            sas_token: self.sas_token.clone(),
+            endpoint: self.endpoint.clone(),
+            number_of_messages: self.number_of_messages,
+            visibility_timeout: self.visibility_timeout,
+            min_poll_interval_ms: self.min_poll_interval_ms,
+            max_poll_interval_ms: self.max_poll_interval_ms,
+            max_dequeue_count: self.max_dequeue_count,
+            poison_queue_name: self.poison_queue_name.clone(),
+            base64_decode: self.base64_decode,
+            unwrap_event_grid: self.unwrap_event_grid,
+            routes: self.routes.clone(),
+            queue_client: self.queue_client.clone(),
+            poison_queue_client: self.poison_queue_client.clone(),
+            poison_queue_created: self.poison_queue_created,
+            messages: Vec::new(),
+            poll_interval_ms: None
+        }
+    }
+}
+impl AzureStorageQueue {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.number_of_messages {
+            // the queue service rejects every receive request outside this range
+            Some(number_of_messages) if !(1..=32).contains(&number_of_messages) => {
+                Err(format!("number_of_messages must be between 1 and 32, got {}", number_of_messages))
+            },
+            _ => Ok(())
+        }
+    }
+    fn account(&self) -> String {
+        let from_connection_string = self.connection_string.as_deref()
+            .and_then(|cs| ConnectionString::new(cs).ok())
+            .and_then(|cs| cs.account_name.map(String::from));
+        from_connection_string.unwrap_or(self.storage_account.clone())
+    }
+    fn location(&self, default: CloudLocation) -> CloudLocation {
+        match &self.endpoint {
+            Some(uri) => CloudLocation::Custom {account: self.account(), uri: uri.clone()},
+            None => default
+        }
+    }
+    /// Where to connect and how to authenticate, from the first of `connection_string`,
+    /// `sas_token` and `account_key` that is set, falling back to the DefaultAzureCredential
+    fn credentials(&self) -> azure_core::Result<(CloudLocation, StorageCredentials)> {
+        let account = self.account();
+        if let Some(connection_string) = &self.connection_string {
+            let connection_string = ConnectionString::new(connection_string)?;
+            if connection_string.use_development_storage == Some(true) {
+                let emulator = CloudLocation::Emulator {
+                    address: EMULATOR_ADDRESS.to_string(), port: EMULATOR_QUEUE_PORT
+                };
+                return Ok((self.location(emulator), StorageCredentials::emulator()))
+            }
+            let protocol = connection_string.default_endpoints_protocol.as_ref()
+                .map(|p| p.to_string()).unwrap_or("https".to_string());
+            let location = match (connection_string.queue_endpoint, connection_string.endpoint_suffix) {
+                (Some(uri), _) => CloudLocation::Custom {account, uri: uri.to_string()},
+                (None, Some(suffix)) => CloudLocation::Custom {
+                    uri: format!("{}://{}.queue.{}", protocol, account, suffix), account
+                },
+                (None, None) => CloudLocation::Public {account}
+            };
+            return Ok((self.location(location), connection_string.storage_credentials()?))
+        }
+        let credentials = match (&self.sas_token, &self.account_key) {
+            (Some(sas_token), _) => StorageCredentials::sas_token(sas_token.as_str())?,
+            (None, Some(key)) => StorageCredentials::access_key(account.clone(), Secret::new(key.clone())),
+            (None, None) => StorageCredentials::token_credential(Arc::new(default_credential()))
+        };
+        Ok((self.location(CloudLocation::Public {account}), credentials))
+    }
+    fn queue_client(&self) -> &QueueClient {
+        self.queue_client.as_ref().expect(
+            "Cannot use the queue without the QueueClient being initialised"
+        )
+    }
+    async fn receive(&self) -> azure_core::Result<Vec<AzureStorageQueueMsg>> {
+        let visibility_timeout = self.visibility_timeout.unwrap_or(DEFAULT_VISIBILITY_TIMEOUT_SECONDS);
+        let mut request = self.queue_client().get_messages()
+            .visibility_timeout(Duration::from_secs(visibility_timeout));
+        if let Some(number_of_messages) = self.number_of_messages {
+            request = request.number_of_messages(number_of_messages);
+        }
+        let messages = request.await?.messages;
+        Ok(messages.into_iter().map(|message| {
+            let mut msg = AzureStorageQueueMsg::leased(self.queue_client(), message, visibility_timeout);
+            (msg.content, msg.attributes) = self.decode(&msg.message.message_text);
+            msg
+        }).collect())
+    }
+    /// Applies the configured decoding to the message text, returning the content along
+    /// with the event type, subject and source of an unwrapped event as attributes.
+    /// Text that cannot be decoded is passed on as it is
+    fn decode(&self, message_text: &str) -> (String, HashMap<String, String>) {
+        let mut content = message_text.to_string();
+        let mut attributes = HashMap::new();
+        if self.base64_decode {
+            if let Ok(bytes) = STANDARD.decode(content.trim()) {
+                content = String::from_utf8_lossy(&bytes).into_owned();
+            }
+        }
+        if self.unwrap_event_grid {
+            if let Ok(serde_json::Value::Object(event)) = serde_json::from_str(&content) {
+                // Event Grid schema names first, then their CloudEvents equivalents
+                for (attribute, keys) in [
+                    ("event_type", &["eventType", "type"][..]),
+                    ("subject", &["subject"]),
+                    ("source", &["topic", "source"])
+                ] {
+                    let value = keys.iter().find_map(|key| event.get(*key).and_then(|v| v.as_str()));
+                    if let Some(value) = value {
+                        attributes.insert(attribute.to_string(), value.to_string());
+                    }
+                }
+                if let Some(data) = event.get("data") {
+                    content = data.to_string();
+                }
+            }
+        }
+        (content, attributes)
+    }
+    /// Waits before polling an empty queue again, backing off exponentially so an idle
+    /// queue costs few requests
+    async fn idle(&mut self) {
+        let min_ms = self.min_poll_interval_ms.unwrap_or(DEFAULT_MIN_POLL_INTERVAL_MS);
+        let max_ms = self.max_poll_interval_ms.unwrap_or(DEFAULT_MAX_POLL_INTERVAL_MS);
+        let wait_ms = self.poll_interval_ms.unwrap_or(min_ms);
+        tokio::time::sleep(Duration::from_millis(wait_ms)).await;
+        self.poll_interval_ms = Some((wait_ms * 2).min(max_ms).max(min_ms));
+    }
+    fn poison_queue_name(&self) -> String {
+        self.poison_queue_name.clone().unwrap_or(format!("{}-poison", self.queue_name))
+    }
+    /// Copies the message to the poison queue along with the error that stopped it from
+    /// triggering, then deletes it from the source queue. The message is left in place
+    /// if the copy fails so that it is not lost
+    async fn poison(&mut self, message: AzureStorageQueueMsg, error: &Error) {
+        let poison_queue_client = self.poison_queue_client.as_ref().expect(
+            "Cannot poison a message when QueueClient not initialised"
+        );
+        if !self.poison_queue_created {
+            // creating a queue that already exists with the same metadata succeeds
+            if let Err(e) = poison_queue_client.create().await {
+                println!("{}: Failed to create poison queue {}: {:?}", self.repr(), self.poison_queue_name(), e);
+                return
+            }
+            self.poison_queue_created = true;
+        }
+        if let Err(e) = poison_queue_client.put_message(poison_message_text(&message.message, error)).await {
+            println!("{}: Failed to move message {} to the poison queue: {:?}", self.repr(), message.message.message_id, e);
+            return
+        }
+        println!("{}: Moved message {} to {}: {}", self.repr(), message.message.message_id, self.poison_queue_name(), error);
+        self.task_done(message).await
+    }
+}
+
+fn poison_message_text(message: &Message, error: &Error) -> String {
+    serde_json::json!({
+        "message_id": message.message_id,
+        "dequeue_count": message.dequeue_count,
+        "error": error.to_string(),
+        "message_text": message.message_text
+    }).to_string()
 }
+
 #[async_trait]
 impl Publisher for AzureStorageQueue {
-    type PubMessage = Message;
+    type PubMessage = AzureStorageQueueMsg;
 
     fn repr(&self) -> String {
-        format!("{}/{}", &self.storage_account, &self.queue_name)
+        format!("{}/{}", self.account(), &self.queue_name)
     }
     async fn init(&mut self) {
-        let account = &self.storage_account;
         let queue_name = &self.queue_name;
-        let credential = Arc::new(DefaultAzureCredential::default());
-        let storage_credentials = StorageCredentials::token_credential(
-            credential
+        let (location, storage_credentials) = self.credentials().expect(
+            "Unable to build the storage credentials from the config"
         );
-        let queue_service = QueueServiceClient::new(account, storage_credentials);
-        let queue_client = queue_service.queue_client(queue_name);
-        // put first messages on internal vec
-        self.messages = Some(queue_client.get_messages().await.unwrap().messages);
-        self.queue_client = Some(queue_client);
-
-    }
-
-    async fn next_message(&mut self) -> Option<Message>{
-        match &self.messages{
-            Some(msgs) => {
-                if msgs.len() == 0 {
-                    let response = self.queue_client.as_ref().expect(
-                        "Cannot await messages without the QueueClient being initialised"
-                    ).get_messages().await.unwrap();
-                    self.messages = Some(response.messages);
+        let queue_service = QueueServiceClientBuilder::with_location(location, storage_credentials).build();
+        self.queue_client = Some(queue_service.queue_client(queue_name));
+        self.poison_queue_client = Some(queue_service.queue_client(self.poison_queue_name()));
+    }
+
+    async fn next_message(&mut self) -> Option<AzureStorageQueueMsg>{
+        if self.messages.is_empty() {
+            match self.receive().await {
+                Ok(messages) if !messages.is_empty() => {
+                    self.poll_interval_ms = None;
+                    // messages are popped from the back so reverse to keep the queue order
+                    self.messages = messages.into_iter().rev().collect();
+                },
+                Ok(_) => self.idle().await,
+                Err(e) => {
+                    println!("{}: Failed to receive messages: {:?}", self.repr(), e);
+                    self.idle().await
                 }
-            },
-            None => {
-                let response = self.queue_client.as_ref().expect(
-                    "Cannot await messages without the QueueClient being initialised"
-                ).get_messages().await.unwrap();
-                self.messages = Some(response.messages);
             }
-        };
-        self.messages.as_mut().expect("Expecting a vec but got None").pop()
+        }
+        self.messages.pop()
     }
-    async fn task_done(&mut self, message: Self::PubMessage) {
-        self.queue_client.as_ref().expect(
-            "Cannot call task done on a message when QueueClient not initialised"
-        ).pop_receipt_client(message).delete().await.expect(
-            "Failed to mark task done"
+    async fn task_done(&mut self, mut message: Self::PubMessage) {
+        let pop_receipt = message.release().await;
+        if let Err(e) = self.queue_client().pop_receipt_client(pop_receipt).delete().await {
+            println!("{}: Failed to delete message {}: {:?}", self.repr(), message.message.message_id, e)
+        }
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, error: &Error) {
+        // otherwise the lease ends with the message and it becomes visible again once its
+        // visibility timeout expires
+        if message.message.dequeue_count >= self.max_dequeue_count.unwrap_or(DEFAULT_MAX_DEQUEUE_COUNT) {
+            self.poison(message, error).await
+        }
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, error: &Error) {
+        // retrying cannot make the message parse
+        self.poison(message, error).await
+    }
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Error, Publisher, RawMessage};
+
+    use super::{poison_message_text, AzureStorageQueue};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use azure_storage_queues::operations::Message;
+    use azure_storage_queues::QueueServiceClientBuilder;
+    use azure_storage::clients::ServiceType;
+    use serde_json::json;
+
+    fn queue_url(config: serde_json::Value) -> String {
+        let queue: AzureStorageQueue = serde_json::from_value(config).unwrap();
+        let (location, _) = queue.credentials().expect("Should build credentials");
+        location.url(ServiceType::Queue).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_credentials_location() {
+        assert_eq!(
+            queue_url(json!({"queue_name": "test", "connection_string": "UseDevelopmentStorage=true"})),
+            "http://127.0.0.1:10001/devstoreaccount1"
+        );
+        assert_eq!(
+            queue_url(json!({
+                "queue_name": "test",
+                "connection_string": "DefaultEndpointsProtocol=https;AccountName=govaccount;AccountKey=a2V5;EndpointSuffix=core.usgovcloudapi.net"
+            })),
+            "https://govaccount.queue.core.usgovcloudapi.net/"
+        );
+        assert_eq!(
+            queue_url(json!({"storage_account": "mystorage", "queue_name": "test", "sas_token": "sv=2022-11-02&sig=abc"})),
+            "https://mystorage.queue.core.windows.net/"
         );
+        assert_eq!(
+            queue_url(json!({
+                "storage_account": "devstoreaccount1",
+                "queue_name": "test",
+                "account_key": "a2V5",
+                "endpoint": "http://azurite:10001/devstoreaccount1"
+            })),
+            "http://azurite:10001/devstoreaccount1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_backoff() {
+        let mut queue: AzureStorageQueue = serde_json::from_value(json!(
+            {"queue_name": "test", "min_poll_interval_ms": 1, "max_poll_interval_ms": 5}
+        )).unwrap();
+        let mut waits = vec![];
+        for _ in 0..5 {
+            queue.idle().await;
+            waits.push(queue.poll_interval_ms.unwrap());
+        }
+        assert_eq!(waits, vec![2, 4, 5, 5, 5]);
+    }
+
+    #[test]
+    fn test_decodes_event_grid_message() {
+        let queue: AzureStorageQueue = serde_json::from_value(json!(
+            {"queue_name": "test", "base64_decode": true, "unwrap_event_grid": true}
+        )).unwrap();
+        let event = json!({
+            "topic": "/subscriptions/abc/resourceGroups/rg/providers/Microsoft.Storage/storageAccounts/mystorage",
+            "subject": "/blobServices/default/containers/uploads/blobs/report.csv",
+            "eventType": "Microsoft.Storage.BlobCreated",
+            "id": "1",
+            "data": {"api": "PutBlob", "url": "https://mystorage.blob.core.windows.net/uploads/report.csv"}
+        });
+        let (content, attributes) = queue.decode(&STANDARD.encode(event.to_string()));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap(), event["data"]);
+        assert_eq!(attributes["event_type"], "Microsoft.Storage.BlobCreated");
+        assert_eq!(attributes["subject"], "/blobServices/default/containers/uploads/blobs/report.csv");
+        assert!(attributes["source"].ends_with("storageAccounts/mystorage"));
+
+        let cloud_event = json!({"type": "Microsoft.Storage.BlobDeleted", "source": "/mystorage", "data": {"api": "DeleteBlob"}});
+        let (content, attributes) = queue.decode(&STANDARD.encode(cloud_event.to_string()));
+        assert_eq!(content, json!({"api": "DeleteBlob"}).to_string());
+        assert_eq!(attributes["event_type"], "Microsoft.Storage.BlobDeleted");
+        assert_eq!(attributes["source"], "/mystorage");
+
+        // plain text is left as it is
+        let plain: AzureStorageQueue = serde_json::from_value(json!({"queue_name": "test"})).unwrap();
+        let (content, attributes) = plain.decode("{\"flow_name\": \"Test Flow\"}");
+        assert_eq!(content, "{\"flow_name\": \"Test Flow\"}");
+        assert!(attributes.is_empty());
+    }
+
+    #[test]
+    fn test_poison_message_text() {
+        let now = azure_core::date::parse_rfc3339("2024-01-01T00:00:00Z").unwrap();
+        let message = Message {
+            message_id: "abc".to_string(),
+            pop_receipt: "receipt".to_string(),
+            insertion_time: now,
+            expiration_time: now,
+            time_next_visible: now,
+            dequeue_count: 5,
+            message_text: "{\"flow_name\": \"Test Flow\"}".to_string()
+        };
+        let error = Error::PrefectApiError("Deployment not found".to_string());
+        let poisoned: serde_json::Value = serde_json::from_str(&poison_message_text(&message, &error)).unwrap();
+        assert_eq!(poisoned, json!({
+            "message_id": "abc",
+            "dequeue_count": 5,
+            "error": error.to_string(),
+            "message_text": "{\"flow_name\": \"Test Flow\"}"
+        }));
     }
 
+    // Requires Azurite, eg. `make start-azurite`
+    #[tokio::test]
+    #[ignore]
+    async fn test_poisons_invalid_message_on_azurite() {
+        let suffix: u32 = rand::random();
+        let mut queue: AzureStorageQueue = serde_json::from_value(json!(
+            {"queue_name": format!("router-test-{}", suffix), "connection_string": "UseDevelopmentStorage=true"}
+        )).unwrap();
+        let (location, credentials) = queue.credentials().unwrap();
+        let queue_service = QueueServiceClientBuilder::with_location(location, credentials).build();
+        let queue_client = queue_service.queue_client(&queue.queue_name);
+        let poison_client = queue_service.queue_client(format!("{}-poison", queue.queue_name));
+        queue_client.create().await.unwrap();
+        queue_client.put_message("not json").await.unwrap();
+
+        queue.init().await;
+        let msg = queue.next_message().await.expect("Should receive the message");
+        queue.task_invalid(msg, &Error::InvalidMessage("not json".to_string())).await;
+        assert!(queue_client.peek_messages().await.unwrap().messages.is_empty());
+        let poisoned = poison_client.peek_messages().await.unwrap().messages;
+        assert_eq!(poisoned.len(), 1);
+        let poisoned: serde_json::Value = serde_json::from_str(&poisoned[0].message_text).unwrap();
+        assert_eq!(poisoned["message_text"], "not json");
+        queue_client.delete().await.unwrap();
+        poison_client.delete().await.unwrap();
+    }
 
-}
\ No newline at end of file
+    // Requires Azurite, eg. `make start-azurite`
+    #[tokio::test]
+    #[ignore]
+    async fn test_renews_visibility_on_azurite() {
+        let suffix: u32 = rand::random();
+        let mut queue: AzureStorageQueue = serde_json::from_value(json!(
+            {"queue_name": format!("router-test-{}", suffix), "connection_string": "UseDevelopmentStorage=true", "visibility_timeout": 2}
+        )).unwrap();
+        let (location, credentials) = queue.credentials().unwrap();
+        let queue_client = QueueServiceClientBuilder::with_location(location, credentials)
+            .build().queue_client(&queue.queue_name);
+        queue_client.create().await.unwrap();
+        queue_client.put_message("slow trigger").await.unwrap();
+
+        queue.init().await;
+        let msg = queue.next_message().await.expect("Should receive the message");
+        // outlive the visibility timeout a few times over
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        assert!(queue_client.peek_messages().await.unwrap().messages.is_empty());
+        queue.task_done(msg).await;
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        assert!(queue_client.peek_messages().await.unwrap().messages.is_empty());
+        queue_client.delete().await.unwrap();
+    }
+
+    // Requires Azurite, eg. `make start-azurite`
+    #[tokio::test]
+    #[ignore]
+    async fn test_receives_from_azurite() {
+        let suffix: u32 = rand::random();
+        let mut queue: AzureStorageQueue = serde_json::from_value(json!(
+            {"queue_name": format!("router-test-{}", suffix), "connection_string": "UseDevelopmentStorage=true"}
+        )).unwrap();
+        let (location, credentials) = queue.credentials().unwrap();
+        let queue_client = QueueServiceClientBuilder::with_location(location, credentials)
+            .build().queue_client(&queue.queue_name);
+        queue_client.create().await.unwrap();
+        let data = json!({"flow_name": "Test Flow", "deployment_name": "test"}).to_string();
+        queue_client.put_message(data.clone()).await.unwrap();
+
+        queue.init().await;
+        let msg = queue.next_message().await.expect("Should receive the message");
+        assert_eq!(msg.get_content_str(), data);
+        queue.task_done(msg).await;
+        let remaining = queue_client.peek_messages().await.unwrap().messages;
+        assert!(remaining.is_empty());
+        queue_client.delete().await.unwrap();
+    }
+}
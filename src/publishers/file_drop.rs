@@ -0,0 +1,348 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+const PROCESSING_DIR: &str = "processing";
+const PROCESSED_DIR: &str = "processed";
+const FAILED_DIR: &str = "failed";
+
+#[derive(Serialize, Deserialize)]
+pub struct FileDrop {
+    pub directory: String,
+    /// Treat each line of a file as a separate message
+    #[serde(default)]
+    pub ndjson: bool,
+    #[serde(skip_serializing, skip_deserializing)]
+    watcher: Option<(RecommendedWatcher, mpsc::UnboundedReceiver<PathBuf>)>,
+    #[serde(skip_serializing, skip_deserializing)]
+    messages: Vec<FileDropMsg>,
+    /// Files with messages that were not settled yet
+    #[serde(skip_serializing, skip_deserializing)]
+    pending: HashMap<PathBuf, PendingFile>
+}
+// the watcher cannot be shared between threads so a clone only carries the config
+impl Clone for FileDrop {
+    fn clone(&self) -> Self {
+        Self {
+            directory: self.directory.clone(),
+            ndjson: self.ndjson,
+            watcher: None,
+            messages: Vec::new(),
+            pending: HashMap::new()
+        }
+    }
+}
+impl fmt::Debug for FileDrop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileDrop")
+            .field("directory", &self.directory)
+            .field("ndjson", &self.ndjson)
+            .field("connected", &self.watcher.is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct PendingFile {
+    remaining: usize,
+    failed: bool
+}
+
+pub struct FileDropMsg {
+    /// Where the file was moved to while its messages are processed
+    path: PathBuf,
+    /// The name the file was dropped with
+    file_name: String,
+    msg: String
+}
+impl RawMessage for FileDropMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::from([("file_name".to_string(), self.file_name.clone())])
+    }
+}
+
+fn is_json_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+/// Files are only picked up once they were closed after writing or moved into the
+/// directory, so partially written files are never read
+fn dropped_file(event: Event) -> Option<PathBuf> {
+    match event.kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) |
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) |
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => event.paths.into_iter().last(),
+        _ => None
+    }
+}
+
+/// The path in `dir` to move the file to, with a timestamp added to its name if a file
+/// that was dropped earlier under the same name is already there
+async fn unused_path(dir: &Path, file_name: &str) -> Result<PathBuf, String> {
+    let path = Path::new(file_name);
+    let target = dir.join(file_name);
+    if !tokio::fs::try_exists(&target).await.map_err(|e| e.to_string())? {
+        return Ok(target)
+    }
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    for attempt in 0.. {
+        let suffix = if attempt == 0 {millis.to_string()} else {format!("{}-{}", millis, attempt)};
+        let target = dir.join(format!("{}-{}{}", stem, suffix, extension));
+        if !tokio::fs::try_exists(&target).await.map_err(|e| e.to_string())? {
+            return Ok(target)
+        }
+    }
+    unreachable!()
+}
+
+impl FileDrop {
+    /// Moves a dropped file into the processing directory, so a file dropped later under
+    /// the same name is not mistaken for one that is still pending
+    async fn claim(&self, path: &Path) -> Result<(PathBuf, String), String> {
+        let file_name = path.file_name().ok_or("Dropped file has no name")?.to_string_lossy().into_owned();
+        let working = unused_path(&Path::new(&self.directory).join(PROCESSING_DIR), &file_name).await?;
+        tokio::fs::rename(path, &working).await.map_err(|e| e.to_string())?;
+        Ok((working, file_name))
+    }
+    async fn read_file(&mut self, path: PathBuf, file_name: String) -> Result<(), String> {
+        let content = tokio::fs::read_to_string(&path).await.map_err(|e| e.to_string())?;
+        let lines: Vec<String> = if self.ndjson {
+            content.lines().filter(|line| !line.trim().is_empty()).map(|line| line.to_string()).collect()
+        } else {
+            vec![content]
+        };
+        if lines.is_empty() {
+            return self.move_file(&path, &file_name, FAILED_DIR).await
+        }
+        self.pending.insert(path.clone(), PendingFile {remaining: lines.len(), failed: false});
+        // messages are popped from the back so reverse to keep the line order
+        for msg in lines.into_iter().rev() {
+            self.messages.push(FileDropMsg {path: path.clone(), file_name: file_name.clone(), msg})
+        }
+        Ok(())
+    }
+    async fn move_file(&self, path: &Path, file_name: &str, dir: &str) -> Result<(), String> {
+        let target = unused_path(&Path::new(&self.directory).join(dir), file_name).await?;
+        tokio::fs::rename(path, target).await.map_err(|e| e.to_string())
+    }
+    /// Moves the file out of the watched directory once all its messages are settled
+    async fn settle(&mut self, message: FileDropMsg, failed: bool) {
+        let done = match self.pending.get_mut(&message.path) {
+            Some(file) => {
+                file.remaining -= 1;
+                file.failed |= failed;
+                file.remaining == 0
+            },
+            None => return
+        };
+        if !done {
+            return
+        }
+        let file = self.pending.remove(&message.path).unwrap_or_default();
+        let dir = if file.failed {FAILED_DIR} else {PROCESSED_DIR};
+        if let Err(e) = self.move_file(&message.path, &message.file_name, dir).await {
+            println!("{}: Failed to move {} to {}: {}", self.repr(), message.path.display(), dir, e)
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for FileDrop {
+    type PubMessage = FileDropMsg;
+
+    fn repr(&self) -> String {
+        format!("FileDrop {}", &self.directory)
+    }
+    async fn init(&mut self) {
+        let directory = PathBuf::from(&self.directory);
+        for dir in [PROCESSING_DIR, PROCESSED_DIR, FAILED_DIR] {
+            std::fs::create_dir_all(directory.join(dir)).expect("Unable to create the processing, processed and failed directories");
+        }
+        // files that were still being processed when the router stopped are read again
+        let mut unfinished: Vec<PathBuf> = std::fs::read_dir(directory.join(PROCESSING_DIR))
+            .expect("Unable to read the processing directory")
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        unfinished.sort();
+        for path in unfinished {
+            let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            if let Err(e) = self.read_file(path.clone(), file_name).await {
+                println!("{}: Failed to read {}: {}", self.repr(), path.display(), e)
+            }
+        }
+        let (sender, files) = mpsc::unbounded_channel();
+        // files dropped while the router was not running are picked up first
+        let mut existing: Vec<PathBuf> = std::fs::read_dir(&directory).expect("Unable to read the drop directory")
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        existing.sort();
+        for path in existing {
+            let _ = sender.send(path);
+        }
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            match event {
+                Ok(event) => if let Some(path) = dropped_file(event) {
+                    let _ = sender.send(path);
+                },
+                Err(e) => println!("FileDrop: Got watcher error {:?}", e)
+            }
+        }).expect("Unable to create the file watcher");
+        watcher.watch(&directory, RecursiveMode::NonRecursive).expect("Unable to watch the drop directory");
+        self.watcher = Some((watcher, files));
+    }
+    async fn next_message(&mut self) -> Option<FileDropMsg> {
+        if self.messages.is_empty() {
+            let (_, files) = self.watcher.as_mut().expect(
+                "Cannot await files without the watcher being initialised"
+            );
+            let path = files.recv().await?;
+            // the same file can be reported more than once, eg. when closed and then renamed,
+            // but it is gone from the drop directory once claimed
+            if !is_json_file(&path) || self.pending.contains_key(&path) || !path.is_file() {
+                return None
+            }
+            let read = match self.claim(&path).await {
+                Ok((working, file_name)) => self.read_file(working, file_name).await,
+                Err(e) => Err(e)
+            };
+            if let Err(e) = read {
+                println!("{}: Failed to read {}: {}", self.repr(), path.display(), e)
+            }
+        }
+        self.messages.pop()
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        self.settle(message, false).await
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, _error: &Error) {
+        self.settle(message, true).await
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        self.settle(message, true).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Error, Publisher, RawMessage};
+
+    use super::FileDrop;
+    use serde_json::json;
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    async fn next(file_drop: &mut FileDrop) -> super::FileDropMsg {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(msg) = file_drop.next_message().await {
+                    return msg
+                }
+            }
+        }).await.expect("Did not pick up the dropped file in time")
+    }
+
+    #[tokio::test]
+    async fn test_moves_ndjson_file_once_settled() {
+        let suffix: u32 = rand::random();
+        let directory = std::env::temp_dir().join(format!("router-file-drop-{}", suffix));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut file_drop: FileDrop = serde_json::from_value(json!(
+            {"directory": directory.to_str().unwrap(), "ndjson": true}
+        )).unwrap();
+        file_drop.init().await;
+
+        let first = json!({"flow_name": "Test Flow", "deployment_name": "first"}).to_string();
+        let second = json!({"flow_name": "Test Flow", "deployment_name": "second"}).to_string();
+        std::fs::write(directory.join("ignored.txt"), "not picked up").unwrap();
+        std::fs::write(directory.join("batch.json"), format!("{}\n{}\n", first, second)).unwrap();
+
+        let msg = next(&mut file_drop).await;
+        assert_eq!(msg.get_content_str(), first);
+        assert_eq!(msg.get_attributes()["file_name"], "batch.json");
+        file_drop.task_done(msg).await;
+        assert!(directory.join("processing").join("batch.json").exists(), "File should wait for all its lines");
+        let msg = next(&mut file_drop).await;
+        assert_eq!(msg.get_content_str(), second);
+        file_drop.task_failed(msg, &Error::PrefectApiError("test".to_string())).await;
+
+        assert!(!directory.join("batch.json").exists());
+        assert!(Path::new(&directory).join("failed").join("batch.json").exists());
+        assert!(directory.join("ignored.txt").exists());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keeps_files_with_the_same_name() {
+        let suffix: u32 = rand::random();
+        let directory = std::env::temp_dir().join(format!("router-file-drop-{}", suffix));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut file_drop: FileDrop = serde_json::from_value(json!(
+            {"directory": directory.to_str().unwrap()}
+        )).unwrap();
+        file_drop.init().await;
+
+        for deployment_name in ["first", "second"] {
+            let data = json!({"flow_name": "Test Flow", "deployment_name": deployment_name}).to_string();
+            std::fs::write(directory.join("event.json"), &data).unwrap();
+            let msg = next(&mut file_drop).await;
+            assert_eq!(msg.get_content_str(), data);
+            file_drop.task_done(msg).await;
+        }
+
+        let mut processed: Vec<String> = std::fs::read_dir(directory.join("processed")).unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        processed.sort();
+        assert_eq!(processed.len(), 2);
+        assert!(processed[0].contains("first") && processed[1].contains("second"));
+        assert!(directory.join("processed").join("event.json").exists());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keeps_file_dropped_under_the_same_name_while_pending() {
+        let suffix: u32 = rand::random();
+        let directory = std::env::temp_dir().join(format!("router-file-drop-{}", suffix));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut file_drop: FileDrop = serde_json::from_value(json!(
+            {"directory": directory.to_str().unwrap()}
+        )).unwrap();
+        file_drop.init().await;
+
+        let first = json!({"flow_name": "Test Flow", "deployment_name": "first"}).to_string();
+        let second = json!({"flow_name": "Test Flow", "deployment_name": "second"}).to_string();
+        std::fs::write(directory.join("event.json"), &first).unwrap();
+        let first_msg = next(&mut file_drop).await;
+        assert_eq!(first_msg.get_content_str(), first);
+        // dropped again before the first file was settled
+        std::fs::write(directory.join("event.json"), &second).unwrap();
+        let second_msg = next(&mut file_drop).await;
+        assert_eq!(second_msg.get_content_str(), second);
+        assert_eq!(second_msg.get_attributes()["file_name"], "event.json");
+        file_drop.task_done(second_msg).await;
+        file_drop.task_done(first_msg).await;
+
+        let mut processed: Vec<String> = std::fs::read_dir(directory.join("processed")).unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        processed.sort();
+        assert_eq!(processed, vec![first, second]);
+        assert_eq!(std::fs::read_dir(directory.join("processing")).unwrap().count(), 0);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
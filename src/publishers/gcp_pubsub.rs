@@ -0,0 +1,310 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use crate::routing::Route;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use gcp_auth::TokenProvider;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+const PUBSUB_SCOPE: &str = "https://www.googleapis.com/auth/pubsub";
+const PUBSUB_URL: &str = "https://pubsub.googleapis.com";
+const DEFAULT_MAX_MESSAGES: i32 = 10;
+const DEFAULT_ACK_DEADLINE_SECONDS: i32 = 60;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PubsubMessage {
+    #[serde(default)]
+    data: String,
+    #[serde(default)]
+    attributes: HashMap<String, String>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReceivedMessage {
+    ack_id: String,
+    message: PubsubMessage
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PullResponse {
+    #[serde(default)]
+    received_messages: Vec<ReceivedMessage>
+}
+
+/// Calls the REST API of a single subscription, either on Google Cloud or the
+/// emulator when `PUBSUB_EMULATOR_HOST` is set
+#[derive(Clone)]
+struct SubscriptionClient {
+    http: reqwest::Client,
+    subscription_url: String,
+    token_provider: Option<Arc<dyn TokenProvider>>
+}
+impl SubscriptionClient {
+    async fn post(&self, action: &str, body: serde_json::Value) -> Result<String, String> {
+        let mut request = self.http
+            .post(format!("{}:{}", &self.subscription_url, action))
+            .json(&body);
+        if let Some(provider) = &self.token_provider {
+            let token = provider.token(&[PUBSUB_SCOPE]).await.map_err(|e| e.to_string())?;
+            request = request.bearer_auth(token.as_str());
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| e.to_string())?;
+        if !status.is_success() {
+            return Err(format!("{} {}", status, text))
+        }
+        Ok(text)
+    }
+    async fn modify_ack_deadline(&self, ack_id: &str, seconds: i32) -> Result<String, String> {
+        self.post("modifyAckDeadline", json!({"ackIds": [ack_id], "ackDeadlineSeconds": seconds})).await
+    }
+    /// Keeps extending the ack deadline of a message until the returned task is aborted
+    fn lease(&self, ack_id: String, seconds: i32) -> JoinHandle<()> {
+        let client = self.clone();
+        tokio::spawn(async move {
+            let every = Duration::from_secs((seconds / 2).max(1) as u64);
+            loop {
+                if let Err(e) = client.modify_ack_deadline(&ack_id, seconds).await {
+                    println!("{}: Failed to extend ack deadline: {}", &client.subscription_url, e)
+                }
+                tokio::time::sleep(every).await;
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GcpPubSub {
+    pub project_id: String,
+    pub subscription: String,
+    pub max_messages: Option<i32>,
+    /// Ack deadline that is kept extended while a message waits for its trigger
+    pub ack_deadline_seconds: Option<i32>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(skip_serializing, skip_deserializing)]
+    client: Option<SubscriptionClient>,
+    #[serde(skip_serializing, skip_deserializing)]
+    messages: Vec<GcpPubSubMsg>
+}
+// in flight messages belong to one thread so a clone only carries the config
+impl Clone for GcpPubSub {
+    fn clone(&self) -> Self {
+        Self {
+            project_id: self.project_id.clone(),
+            subscription: self.subscription.clone(),
+            max_messages: self.max_messages,
+            ack_deadline_seconds: self.ack_deadline_seconds,
+            routes: self.routes.clone(),
+            client: None,
+            messages: Vec::new()
+        }
+    }
+}
+impl fmt::Debug for GcpPubSub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcpPubSub")
+            .field("project_id", &self.project_id)
+            .field("subscription", &self.subscription)
+            .field("max_messages", &self.max_messages)
+            .field("ack_deadline_seconds", &self.ack_deadline_seconds)
+            .field("routes", &self.routes)
+            .field("connected", &self.client.is_some())
+            .finish()
+    }
+}
+
+pub struct GcpPubSubMsg {
+    ack_id: String,
+    msg: String,
+    attributes: HashMap<String, String>,
+    lease: Option<JoinHandle<()>>
+}
+impl GcpPubSubMsg {
+    fn from_received(received: ReceivedMessage) -> Self {
+        let msg = match STANDARD.decode(&received.message.data) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(_) => received.message.data
+        };
+        Self {
+            ack_id: received.ack_id,
+            msg,
+            attributes: received.message.attributes,
+            lease: None
+        }
+    }
+}
+impl Drop for GcpPubSubMsg {
+    fn drop(&mut self) {
+        if let Some(lease) = self.lease.take() {
+            lease.abort()
+        }
+    }
+}
+impl RawMessage for GcpPubSubMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        self.attributes.clone()
+    }
+}
+
+impl GcpPubSub {
+    /// Finds the credentials, which can fail while eg. the metadata server is not yet reachable
+    async fn connect(&mut self) -> Result<(), String> {
+        let (base_url, token_provider) = match std::env::var("PUBSUB_EMULATOR_HOST") {
+            Ok(host) => (format!("http://{}", host), None),
+            Err(_) => (
+                PUBSUB_URL.to_string(),
+                Some(gcp_auth::provider().await.map_err(|e| format!("Unable to find Google Cloud credentials: {}", e))?)
+            )
+        };
+        self.client = Some(SubscriptionClient {
+            http: reqwest::Client::new(),
+            subscription_url: format!(
+                "{}/v1/projects/{}/subscriptions/{}", base_url, &self.project_id, &self.subscription
+            ),
+            token_provider
+        });
+        Ok(())
+    }
+    fn client(&self) -> &SubscriptionClient {
+        self.client.as_ref().expect(
+            "Cannot use the subscription without the Pub/Sub client being initialised"
+        )
+    }
+    async fn pull(&mut self) -> Result<(), String> {
+        if self.client.is_none() {
+            self.connect().await?;
+        }
+        let client = self.client().clone();
+        let max_messages = self.max_messages.unwrap_or(DEFAULT_MAX_MESSAGES);
+        let text = client.post("pull", json!({"maxMessages": max_messages})).await?;
+        let response: PullResponse = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        let deadline = self.ack_deadline_seconds.unwrap_or(DEFAULT_ACK_DEADLINE_SECONDS);
+        // messages are popped from the back so reverse to keep the pulled order
+        for received in response.received_messages.into_iter().rev() {
+            let mut msg = GcpPubSubMsg::from_received(received);
+            msg.lease = Some(client.lease(msg.ack_id.clone(), deadline));
+            self.messages.push(msg);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Publisher for GcpPubSub {
+    type PubMessage = GcpPubSubMsg;
+
+    fn repr(&self) -> String {
+        format!("PubSub {}/{}", &self.project_id, &self.subscription)
+    }
+    async fn init(&mut self) {
+        // retried on the next pull if the credentials are not available yet
+        if let Err(e) = self.connect().await {
+            println!("{}: {}", self.repr(), e)
+        }
+    }
+    async fn next_message(&mut self) -> Option<GcpPubSubMsg> {
+        if self.messages.is_empty() {
+            if let Err(e) = self.pull().await {
+                println!("{}: Failed to pull messages: {}", self.repr(), e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+        self.messages.pop()
+    }
+    async fn task_done(&mut self, mut message: Self::PubMessage) {
+        if let Some(lease) = message.lease.take() {
+            lease.abort()
+        }
+        let acked = self.client().post("acknowledge", json!({"ackIds": [&message.ack_id]})).await;
+        if let Err(e) = acked {
+            println!("{}: Failed to ack message: {}", self.repr(), e)
+        }
+    }
+    async fn task_failed(&mut self, mut message: Self::PubMessage, _error: &Error) {
+        if let Some(lease) = message.lease.take() {
+            lease.abort()
+        }
+        // a zero deadline makes the message available for redelivery straight away
+        if let Err(e) = self.client().modify_ack_deadline(&message.ack_id, 0).await {
+            println!("{}: Failed to nack message: {}", self.repr(), e)
+        }
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        self.task_done(message).await
+    }
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Publisher, RawMessage};
+
+    use super::{GcpPubSub, GcpPubSubMsg, PullResponse};
+    use serde_json::json;
+
+    #[test]
+    fn test_decodes_pulled_message() {
+        let response: PullResponse = serde_json::from_value(json!(
+            {
+                "receivedMessages": [{
+                    "ackId": "ack-1",
+                    "message": {
+                        "data": "eyJ0ZW1wIjogMjF9",
+                        "attributes": {"eventType": "sensor"},
+                        "messageId": "1"
+                    }
+                }]
+            }
+        )).unwrap();
+        let received = response.received_messages.into_iter().next().unwrap();
+        let msg = GcpPubSubMsg::from_received(received);
+        assert_eq!(msg.ack_id, "ack-1");
+        assert_eq!(msg.get_content_str(), "{\"temp\": 21}");
+        assert_eq!(msg.get_attributes()["eventType"], "sensor");
+    }
+
+    // Requires the Pub/Sub emulator, eg. `make start-pubsub-emulator` and
+    // PUBSUB_EMULATOR_HOST=127.0.0.1:8085
+    #[tokio::test]
+    #[ignore]
+    async fn test_e2e_emulator() {
+        let host = std::env::var("PUBSUB_EMULATOR_HOST").expect("PUBSUB_EMULATOR_HOST must be set");
+        let suffix: u32 = rand::random();
+        let project = "router-test";
+        let topic_url = format!("http://{}/v1/projects/{}/topics/topic-{}", host, project, suffix);
+        let http = reqwest::Client::new();
+        http.put(&topic_url).send().await.unwrap().error_for_status().unwrap();
+        http.put(format!("http://{}/v1/projects/{}/subscriptions/sub-{}", host, project, suffix))
+            .json(&json!({"topic": format!("projects/{}/topics/topic-{}", project, suffix)}))
+            .send().await.unwrap().error_for_status().unwrap();
+        http.post(format!("{}:publish", topic_url))
+            .json(&json!({"messages": [{"data": "eyJ0ZW1wIjogMjF9", "attributes": {"eventType": "sensor"}}]}))
+            .send().await.unwrap().error_for_status().unwrap();
+
+        let mut pubsub: GcpPubSub = serde_json::from_value(json!(
+            {"project_id": project, "subscription": format!("sub-{}", suffix), "ack_deadline_seconds": 10}
+        )).unwrap();
+        pubsub.init().await;
+        let msg = pubsub.next_message().await.expect("Should pull the published message");
+        assert_eq!(msg.get_content_str(), "{\"temp\": 21}");
+        assert_eq!(msg.get_attributes()["eventType"], "sensor");
+        pubsub.task_done(msg).await;
+        assert!(pubsub.next_message().await.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::interfaces::{Error, FlowRun, Publisher, RawMessage};
+use crate::routing::Route;
+use super::http_webhook::{HttpRequestMsg, HttpWebhook};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use hmac::{Hmac, Mac};
+use hyper::{HeaderMap, StatusCode};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+const DEFAULT_PATH: &str = "/";
+
+/// An `HttpWebhook` that only accepts requests signed with the `secret`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GitWebhook {
+    #[serde(flatten)]
+    pub webhook: HttpWebhook,
+    /// The webhook secret on GitHub or the secret token on GitLab
+    pub secret: String
+}
+impl fmt::Debug for GitWebhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GitWebhook")
+            .field("webhook", &self.webhook)
+            .finish()
+    }
+}
+
+/// A webhook request, passed on as the `payload` parameter of the triggered flow
+pub struct GitEventMsg(HttpRequestMsg);
+impl RawMessage for GitEventMsg {
+    fn get_content_str(&self) -> String {
+        let body = self.0.get_content_str();
+        let payload = serde_json::from_str(&body).unwrap_or(serde_json::Value::String(body));
+        json!({"payload": payload}).to_string()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        self.0.get_attributes()
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Checks the `sha256=<hex>` HMAC GitHub signs the body with
+fn verify_github_signature(secret: &str, signature: &str, body: &[u8]) -> bool {
+    let expected = match signature.strip_prefix("sha256=").map(hex::decode) {
+        Some(Ok(expected)) => expected,
+        _ => return false
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Checks the `X-Gitlab-Token`, comparing digests so the time taken does not depend on the
+/// length of the secret
+fn verify_gitlab_token(secret: &str, token: &str) -> bool {
+    Sha256::digest(token.as_bytes()).ct_eq(&Sha256::digest(secret.as_bytes())).into()
+}
+
+/// Maps the provider specific event names onto `push`, `tag` and `pull_request`.
+/// Other events keep the name from their header
+fn event_kind(github_event: Option<&str>, gitlab_event: Option<&str>, git_ref: Option<&str>) -> String {
+    match (github_event, gitlab_event) {
+        (Some("push"), _) if git_ref.is_some_and(|r| r.starts_with("refs/tags/")) => "tag".to_string(),
+        (Some("push"), _) | (_, Some("Push Hook")) => "push".to_string(),
+        (_, Some("Tag Push Hook")) => "tag".to_string(),
+        (Some("pull_request"), _) | (_, Some("Merge Request Hook")) => "pull_request".to_string(),
+        (Some(event), _) | (_, Some(event)) => event.to_string(),
+        (None, None) => String::new()
+    }
+}
+
+impl GitWebhook {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.secret.is_empty() {
+            // an empty HMAC key or token would let anyone trigger flows
+            return Err("GitWebhook secret must not be empty".to_string())
+        }
+        self.webhook.validate()
+    }
+}
+
+/// Verifies the request came from GitHub or GitLab and describes the event for routing
+fn check_request(secret: &str, headers: &HeaderMap, body: &[u8]) -> Result<HashMap<String, String>, (StatusCode, String)> {
+    let provider = if let Some(signature) = header(headers, "x-hub-signature-256") {
+        if !verify_github_signature(secret, signature, body) {
+            return Err((StatusCode::UNAUTHORIZED, "Invalid X-Hub-Signature-256".to_string()))
+        }
+        "github"
+    } else if let Some(token) = header(headers, "x-gitlab-token") {
+        if !verify_gitlab_token(secret, token) {
+            return Err((StatusCode::UNAUTHORIZED, "Invalid X-Gitlab-Token".to_string()))
+        }
+        "gitlab"
+    } else {
+        return Err((StatusCode::UNAUTHORIZED, "Missing X-Hub-Signature-256 or X-Gitlab-Token".to_string()))
+    };
+    let payload: serde_json::Value = serde_json::from_slice(body).unwrap_or_default();
+    let git_ref = payload["ref"].as_str();
+    let github_event = header(headers, "x-github-event");
+    let gitlab_event = header(headers, "x-gitlab-event");
+
+    let mut attributes = HashMap::from([
+        ("provider".to_string(), provider.to_string()),
+        ("event".to_string(), event_kind(github_event, gitlab_event, git_ref))
+    ]);
+    if let Some(git_ref) = git_ref {
+        attributes.insert("ref".to_string(), git_ref.to_string());
+    }
+    let action = payload["action"].as_str().or(payload["object_attributes"]["action"].as_str());
+    if let Some(action) = action {
+        attributes.insert("action".to_string(), action.to_string());
+    }
+    let repository = payload["repository"]["full_name"].as_str().or(payload["project"]["path_with_namespace"].as_str());
+    if let Some(repository) = repository {
+        attributes.insert("repository".to_string(), repository.to_string());
+    }
+    Ok(attributes)
+}
+
+#[async_trait]
+impl Publisher for GitWebhook {
+    type PubMessage = GitEventMsg;
+
+    fn repr(&self) -> String {
+        format!("Git webhook {}{}", &self.webhook.bind_address, self.webhook.path.as_deref().unwrap_or(DEFAULT_PATH))
+    }
+    async fn init(&mut self) {
+        let secret = self.secret.clone();
+        self.webhook.set_check(Arc::new(move |headers, body| check_request(&secret, headers, body)));
+        self.webhook.init().await
+    }
+    async fn next_message(&mut self) -> Option<GitEventMsg> {
+        self.webhook.next_message().await.map(GitEventMsg)
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        message.0.respond(StatusCode::ACCEPTED, json!({}))
+    }
+    async fn task_triggered(&mut self, message: Self::PubMessage, flow_run: &FlowRun) {
+        message.0.respond_flow_run(flow_run)
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, error: &Error) {
+        message.0.respond(StatusCode::BAD_GATEWAY, json!({"error": error.to_string()}))
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, error: &Error) {
+        // events without a matching route are expected, so they are acknowledged rather than
+        // reported to the provider as failed deliveries
+        message.0.respond(StatusCode::ACCEPTED, json!({"ignored": error.to_string()}))
+    }
+    fn routes(&self) -> &[Route] {
+        self.webhook.routes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Publisher, RawMessage};
+
+    use super::{event_kind, verify_gitlab_token, GitWebhook};
+    use hmac::{Hmac, Mac};
+    use serde_json::json;
+    use sha2::Sha256;
+
+    #[test]
+    fn test_event_kind() {
+        assert_eq!(event_kind(Some("push"), None, Some("refs/heads/main")), "push");
+        assert_eq!(event_kind(Some("push"), None, Some("refs/tags/v1.0.0")), "tag");
+        assert_eq!(event_kind(Some("pull_request"), None, None), "pull_request");
+        assert_eq!(event_kind(None, Some("Tag Push Hook"), Some("refs/tags/v1.0.0")), "tag");
+        assert_eq!(event_kind(None, Some("Merge Request Hook"), None), "pull_request");
+        assert_eq!(event_kind(Some("issues"), None, None), "issues");
+    }
+
+    #[test]
+    fn test_verify_gitlab_token() {
+        assert!(verify_gitlab_token("s3cret", "s3cret"));
+        assert!(!verify_gitlab_token("s3cret", "s3cre"));
+        assert!(!verify_gitlab_token("s3cret", ""));
+    }
+
+    #[tokio::test]
+    async fn test_verifies_github_signature() {
+        let mut webhook: GitWebhook = serde_json::from_value(json!(
+            {"bind_address": "127.0.0.1:0", "secret": "s3cret"}
+        )).unwrap();
+        webhook.init().await;
+        let url = format!("http://{}/", webhook.webhook.local_addr.unwrap());
+        let body = json!({"ref": "refs/tags/v1.0.0", "repository": {"full_name": "org/repo"}}).to_string();
+
+        let client = reqwest::Client::new();
+        let response = client.post(&url)
+            .header("X-GitHub-Event", "push")
+            .header("X-Hub-Signature-256", "sha256=00")
+            .body(body.clone())
+            .send().await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+        mac.update(body.as_bytes());
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        let request = tokio::spawn(
+            client.post(url)
+                .header("X-GitHub-Event", "push")
+                .header("X-Hub-Signature-256", signature)
+                .body(body.clone())
+                .send()
+        );
+        let msg = webhook.next_message().await.expect("Should accept the signed request");
+        let attributes = msg.get_attributes();
+        assert_eq!(attributes["event"], "tag");
+        assert_eq!(attributes["provider"], "github");
+        assert_eq!(attributes["repository"], "org/repo");
+        assert_eq!(attributes["x-github-event"], "push");
+        let content: serde_json::Value = serde_json::from_str(&msg.get_content_str()).unwrap();
+        assert_eq!(content["payload"]["repository"]["full_name"], "org/repo");
+        webhook.task_done(msg).await;
+        assert_eq!(request.await.unwrap().unwrap().status(), 202);
+    }
+}
@@ -0,0 +1,404 @@
+use crate::interfaces::{Error, FlowRun, Publisher, RawMessage};
+use crate::routing::Route;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+const DEFAULT_PATH: &str = "/";
+// requests waiting for their flow run to be triggered
+const CHANNEL_CAPACITY: usize = 100;
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+// wait before binding again after the server stopped
+const RESTART_INTERVAL_MS: u64 = 1000;
+
+/// Checks a request before it is queued, returning any extra attributes for routing or
+/// the status and error to reject it with
+pub type RequestCheck = Arc<
+    dyn Fn(&HeaderMap, &[u8]) -> Result<HashMap<String, String>, (StatusCode, String)> + Send + Sync
+>;
+
+#[derive(Serialize, Deserialize)]
+pub struct HttpWebhook {
+    /// Address the server listens on, eg. `0.0.0.0:8080`
+    pub bind_address: String,
+    /// Only requests to this path are accepted. Defaults to `/`
+    pub path: Option<String>,
+    /// Larger request bodies are rejected with a `413`. Defaults to 1 MiB
+    pub max_body_bytes: Option<usize>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Accepts every request when not set
+    #[serde(skip_serializing, skip_deserializing)]
+    check: Option<RequestCheck>,
+    #[serde(skip_serializing, skip_deserializing)]
+    requests: Option<mpsc::Receiver<HttpRequestMsg>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub(super) local_addr: Option<SocketAddr>,
+    #[serde(skip_serializing, skip_deserializing)]
+    server: Option<JoinHandle<()>>
+}
+// the server is bound by the publisher that was initialised so a clone only carries the config
+impl Clone for HttpWebhook {
+    fn clone(&self) -> Self {
+        Self {
+            bind_address: self.bind_address.clone(),
+            path: self.path.clone(),
+            max_body_bytes: self.max_body_bytes,
+            routes: self.routes.clone(),
+            check: self.check.clone(),
+            requests: None,
+            local_addr: None,
+            server: None
+        }
+    }
+}
+impl fmt::Debug for HttpWebhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpWebhook")
+            .field("bind_address", &self.bind_address)
+            .field("path", &self.path)
+            .field("max_body_bytes", &self.max_body_bytes)
+            .field("routes", &self.routes)
+            .field("connected", &self.local_addr.is_some())
+            .finish()
+    }
+}
+impl Drop for HttpWebhook {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.abort()
+        }
+    }
+}
+
+/// A request held open until the router has tried to trigger its flow run
+pub struct HttpRequestMsg {
+    msg: String,
+    attributes: HashMap<String, String>,
+    responder: oneshot::Sender<Response<Body>>
+}
+impl HttpRequestMsg {
+    pub fn respond(self, status: StatusCode, body: serde_json::Value) {
+        // the client may have gone away in the meantime, in which case there is no one to tell
+        let _ = self.responder.send(json_response(status, body));
+    }
+    pub fn respond_flow_run(self, flow_run: &FlowRun) {
+        self.respond(
+            StatusCode::ACCEPTED, json!({"flow_run_id": &flow_run.id, "flow_run_name": &flow_run.name})
+        )
+    }
+}
+impl RawMessage for HttpRequestMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        self.attributes.clone()
+    }
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("Response should always be valid")
+}
+
+/// The path and headers of a request, with header names in lower case
+fn request_attributes(req: &Request<Body>) -> HashMap<String, String> {
+    let mut attributes: HashMap<String, String> = req.headers().iter()
+        .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    attributes.insert("path".to_string(), req.uri().path().to_string());
+    attributes
+}
+
+/// Reads the body, or returns the response to reject it with if it is longer than `max_bytes`
+async fn read_body(mut body: Body, max_bytes: usize) -> Result<Vec<u8>, Response<Body>> {
+    let too_large = || json_response(
+        StatusCode::PAYLOAD_TOO_LARGE, json!({"error": format!("Body is larger than {} bytes", max_bytes)})
+    );
+    if body.size_hint().lower() > max_bytes as u64 {
+        return Err(too_large())
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})))?;
+        if bytes.len() + chunk.len() > max_bytes {
+            return Err(too_large())
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+async fn handle(
+    req: Request<Body>,
+    path: String,
+    max_body_bytes: usize,
+    check: RequestCheck,
+    sender: mpsc::Sender<HttpRequestMsg>
+) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() != path {
+        return Ok(json_response(StatusCode::NOT_FOUND, json!({"error": "Not found"})))
+    }
+    if req.method() != Method::POST {
+        return Ok(json_response(StatusCode::METHOD_NOT_ALLOWED, json!({"error": "Only POST is supported"})))
+    }
+    let mut attributes = request_attributes(&req);
+    let headers = req.headers().clone();
+    let bytes = match read_body(req.into_body(), max_body_bytes).await {
+        Ok(bytes) => bytes,
+        Err(response) => return Ok(response)
+    };
+    match check(&headers, &bytes) {
+        Ok(extra) => attributes.extend(extra),
+        Err((status, error)) => return Ok(json_response(status, json!({"error": error})))
+    }
+    let msg = String::from_utf8_lossy(&bytes).into_owned();
+    let (responder, response) = oneshot::channel();
+    if sender.send(HttpRequestMsg {msg, attributes, responder}).await.is_err() {
+        return Ok(json_response(StatusCode::SERVICE_UNAVAILABLE, json!({"error": "Router is not running"})))
+    }
+    Ok(response.await.unwrap_or_else(|_| {
+        json_response(StatusCode::SERVICE_UNAVAILABLE, json!({"error": "Router is not running"}))
+    }))
+}
+
+/// Binds the server and queues every accepted `POST` to `path` as a message
+fn serve(
+    bind_address: &str, path: String, max_body_bytes: usize, check: RequestCheck, repr: String
+) -> Result<(SocketAddr, JoinHandle<()>, mpsc::Receiver<HttpRequestMsg>), String> {
+    let addr: SocketAddr = bind_address.parse().map_err(|e| format!("Invalid bind_address: {}", e))?;
+    let (sender, requests) = mpsc::channel(CHANNEL_CAPACITY);
+    let make_service = make_service_fn(move |_| {
+        let path = path.clone();
+        let check = check.clone();
+        let sender = sender.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle(req, path.clone(), max_body_bytes, check.clone(), sender.clone())
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr).map_err(|e| format!("Unable to bind the HTTP server: {}", e))?
+        .serve(make_service);
+    let local_addr = server.local_addr();
+    let server = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            println!("{}: HTTP server stopped: {:?}", repr, e)
+        }
+    });
+    Ok((local_addr, server, requests))
+}
+
+impl HttpWebhook {
+    pub fn validate(&self) -> Result<(), String> {
+        self.bind_address.parse::<SocketAddr>().map(|_| ()).map_err(|e| format!("Invalid bind_address: {}", e))
+    }
+    /// Sets the check requests must pass before they are queued
+    #[cfg_attr(not(feature = "git_webhook"), allow(dead_code))]
+    pub fn set_check(&mut self, check: RequestCheck) {
+        self.check = Some(check)
+    }
+    fn start(&mut self) -> Result<(), String> {
+        let path = self.path.clone().unwrap_or(DEFAULT_PATH.to_string());
+        let check = self.check.clone().unwrap_or_else(|| Arc::new(|_, _| Ok(HashMap::new())));
+        let max_body_bytes = self.max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES);
+        let (local_addr, server, requests) = serve(&self.bind_address, path, max_body_bytes, check, self.repr())?;
+        self.local_addr = Some(local_addr);
+        self.server = Some(server);
+        self.requests = Some(requests);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Publisher for HttpWebhook {
+    type PubMessage = HttpRequestMsg;
+
+    fn repr(&self) -> String {
+        format!("HTTP {}{}", &self.bind_address, self.path.as_deref().unwrap_or(DEFAULT_PATH))
+    }
+    async fn init(&mut self) {
+        // eg. an address still in use is retried by next_message
+        if let Err(e) = self.start() {
+            println!("{}: {}", self.repr(), e)
+        }
+    }
+    async fn next_message(&mut self) -> Option<HttpRequestMsg> {
+        let requests = match self.requests.as_mut() {
+            Some(requests) => requests,
+            None => {
+                tokio::time::sleep(Duration::from_millis(RESTART_INTERVAL_MS)).await;
+                if let Err(e) = self.start() {
+                    println!("{}: {}", self.repr(), e)
+                }
+                return None
+            }
+        };
+        let request = requests.recv().await;
+        if request.is_none() {
+            // the channel only closes once the server task has ended
+            println!("{}: HTTP server stopped, restarting", self.repr());
+            self.requests = None;
+            self.local_addr = None;
+        }
+        request
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        message.respond(StatusCode::ACCEPTED, json!({}))
+    }
+    async fn task_triggered(&mut self, message: Self::PubMessage, flow_run: &FlowRun) {
+        message.respond_flow_run(flow_run)
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, error: &Error) {
+        message.respond(StatusCode::BAD_GATEWAY, json!({"error": error.to_string()}))
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, error: &Error) {
+        message.respond(StatusCode::BAD_REQUEST, json!({"error": error.to_string()}))
+    }
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Error, FlowRun, Publisher, RawMessage};
+    use crate::prefect;
+
+    use super::{json_response, HttpWebhook};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Server, StatusCode};
+    use serde_json::json;
+    use std::convert::Infallible;
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+
+    async fn start_webhook() -> (HttpWebhook, String) {
+        let mut webhook: HttpWebhook = serde_json::from_value(json!(
+            {"bind_address": "127.0.0.1:0", "path": "/events"}
+        )).unwrap();
+        webhook.init().await;
+        let url = format!("http://{}/events", webhook.local_addr.unwrap());
+        (webhook, url)
+    }
+
+    #[tokio::test]
+    async fn test_responds_with_flow_run() {
+        let (mut webhook, url) = start_webhook().await;
+        let data = json!({"flow_name": "Test Flow", "deployment_name": "test"}).to_string();
+        let request = tokio::spawn(
+            reqwest::Client::new().post(url).header("X-Event", "sensor").body(data.clone()).send()
+        );
+
+        let msg = webhook.next_message().await.expect("Should receive the posted body");
+        assert_eq!(msg.get_content_str(), data);
+        assert_eq!(msg.get_attributes()["x-event"], "sensor");
+        assert_eq!(msg.get_attributes()["path"], "/events");
+        let flow_run = FlowRun {id: "1234".to_string(), name: "brave-fox".to_string()};
+        webhook.task_triggered(msg, &flow_run).await;
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), 202);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body, json!({"flow_run_id": "1234", "flow_run_name": "brave-fox"}));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_messages_and_other_methods() {
+        let (mut webhook, url) = start_webhook().await;
+        let response = reqwest::Client::new().get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 405);
+
+        let request = tokio::spawn(reqwest::Client::new().post(url).body("not json").send());
+        let msg = webhook.next_message().await.unwrap();
+        webhook.task_invalid(msg, &Error::InvalidMessage("test".to_string())).await;
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_restarts_stopped_server() {
+        let (mut webhook, _) = start_webhook().await;
+        webhook.server.as_ref().unwrap().abort();
+        assert!(webhook.next_message().await.is_none());
+        assert!(webhook.local_addr.is_none());
+        assert!(webhook.next_message().await.is_none(), "Should bind a new server");
+
+        let url = format!("http://{}/events", webhook.local_addr.expect("Server should be running again"));
+        let request = tokio::spawn(reqwest::Client::new().post(url).body("{}").send());
+        let msg = webhook.next_message().await.expect("New server should queue requests");
+        webhook.task_done(msg).await;
+        assert_eq!(request.await.unwrap().unwrap().status(), 202);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_large_bodies() {
+        let mut webhook: HttpWebhook = serde_json::from_value(json!(
+            {"bind_address": "127.0.0.1:0", "path": "/events", "max_body_bytes": 16}
+        )).unwrap();
+        webhook.init().await;
+        let url = format!("http://{}/events", webhook.local_addr.unwrap());
+        let response = reqwest::Client::new().post(&url).body("x".repeat(17)).send().await.unwrap();
+        assert_eq!(response.status(), 413);
+
+        // a chunked body has no content length up front
+        let addr = webhook.local_addr.unwrap();
+        let response = tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            let chunk = "x".repeat(10);
+            write!(
+                stream,
+                "POST /events HTTP/1.1\r\nHost: {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+                a\r\n{}\r\na\r\n{}\r\n0\r\n\r\n",
+                addr, chunk, chunk
+            ).unwrap();
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            response
+        }).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 413"), "Got {}", response);
+    }
+
+    #[tokio::test]
+    async fn test_responds_bad_gateway_when_prefect_rejects_the_trigger() {
+        // a Prefect API that rejects every request
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_| async {
+                Ok::<_, Infallible>(json_response(StatusCode::UNPROCESSABLE_ENTITY, json!({"detail": "Invalid"})))
+            }))
+        });
+        let prefect_api = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let prefect_uri = format!("http://{}/api", prefect_api.local_addr());
+        tokio::spawn(prefect_api);
+
+        let (mut webhook, url) = start_webhook().await;
+        let data = json!({"flow_name": "Test Flow", "deployment_name": "test"}).to_string();
+        let request = tokio::spawn(reqwest::Client::new().post(url).body(data).send());
+        let msg = webhook.next_message().await.unwrap();
+        let settings = Arc::new(serde_json::from_value(json!({})).unwrap());
+        let error = prefect::trigger_deployment_at(&prefect_uri, "Test Flow", "test", &None, &settings)
+            .await.expect_err("A 422 from Prefect should fail the trigger");
+        assert!(matches!(error, Error::PrefectApiError(_)));
+        webhook.task_failed(msg, &error).await;
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), 502);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].as_str().unwrap().contains("422"));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::{Message, OwnedMessage};
+use rdkafka::{Offset, TopicPartitionList};
+
+const DEFAULT_RETRY_DELAY_MS: u64 = 5000;
+const SEEK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize)]
+pub struct Kafka {
+    pub bootstrap_servers: String,
+    pub group_id: String,
+    pub topics: Vec<String>,
+    /// Any additional librdkafka client properties, eg. `security.protocol`
+    pub client_config: Option<HashMap<String, String>>,
+    /// Wait before a record whose trigger failed is consumed again
+    pub retry_delay_ms: Option<u64>,
+    #[serde(skip_serializing, skip_deserializing)]
+    consumer: Option<StreamConsumer>
+}
+// the consumer cannot be shared between threads so a clone only carries the config
+impl Clone for Kafka {
+    fn clone(&self) -> Self {
+        Self {
+            bootstrap_servers: self.bootstrap_servers.clone(),
+            group_id: self.group_id.clone(),
+            topics: self.topics.clone(),
+            client_config: self.client_config.clone(),
+            retry_delay_ms: self.retry_delay_ms,
+            consumer: None
+        }
+    }
+}
+impl fmt::Debug for Kafka {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Kafka")
+            .field("bootstrap_servers", &self.bootstrap_servers)
+            .field("group_id", &self.group_id)
+            .field("topics", &self.topics)
+            .field("client_config", &self.client_config)
+            .field("retry_delay_ms", &self.retry_delay_ms)
+            .field("connected", &self.consumer.is_some())
+            .finish()
+    }
+}
+
+pub struct KafkaMsg {
+    topic: String,
+    partition: i32,
+    offset: i64,
+    msg: String
+}
+impl KafkaMsg {
+    fn from_owned(message: OwnedMessage) -> Self {
+        let msg = match message.payload() {
+            Some(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            None => String::new()
+        };
+        Self {
+            topic: message.topic().to_string(),
+            partition: message.partition(),
+            offset: message.offset(),
+            msg
+        }
+    }
+    /// The committed offset is the position of the next record to consume
+    fn commit_list(&self) -> TopicPartitionList {
+        let mut tpl = TopicPartitionList::new();
+        tpl.add_partition_offset(&self.topic, self.partition, Offset::Offset(self.offset + 1))
+            .expect("Offset should always be valid for a consumed record");
+        tpl
+    }
+}
+impl RawMessage for KafkaMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+}
+
+#[async_trait]
+impl Publisher for Kafka {
+    type PubMessage = KafkaMsg;
+
+    fn repr(&self) -> String {
+        format!("Kafka {}/{}", self.group_id, self.topics.join(","))
+    }
+    async fn init(&mut self) {
+        let mut client_config = ClientConfig::new();
+        if let Some(extra) = &self.client_config {
+            for (key, value) in extra {
+                client_config.set(key, value);
+            }
+        }
+        // offsets are only committed once the flow run was created
+        let consumer: StreamConsumer = client_config
+            .set("bootstrap.servers", &self.bootstrap_servers)
+            .set("group.id", &self.group_id)
+            .set("enable.auto.commit", "false")
+            .create()
+            .expect("Unable to create Kafka consumer from the given config");
+        let topics: Vec<&str> = self.topics.iter().map(|t| t.as_str()).collect();
+        consumer.subscribe(&topics).expect("Unable to subscribe to Kafka topics");
+        self.consumer = Some(consumer)
+    }
+    async fn next_message(&mut self) -> Option<KafkaMsg> {
+        let consumer = self.consumer.as_ref().expect(
+            "Cannot await messages without the consumer being initialised"
+        );
+        match consumer.recv().await {
+            Ok(message) => Some(KafkaMsg::from_owned(message.detach())),
+            Err(e) => {
+                println!("{}: Got consumer error {:?}", self.repr(), e);
+                None
+            }
+        }
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        let consumer = self.consumer.as_ref().expect(
+            "Cannot call task done on a message when the consumer is not initialised"
+        );
+        if let Err(e) = consumer.commit(&message.commit_list(), CommitMode::Async) {
+            println!(
+                "{}: Failed to commit offset {} for {}/{}: {:?}",
+                self.repr(), message.offset, message.topic, message.partition, e
+            )
+        }
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, _error: &Error) {
+        // rewinds the partition so the failed record is consumed again before any later
+        // record can commit past it
+        let delay = Duration::from_millis(self.retry_delay_ms.unwrap_or(DEFAULT_RETRY_DELAY_MS));
+        tokio::time::sleep(delay).await;
+        let consumer = self.consumer.as_ref().expect(
+            "Cannot call task failed on a message when the consumer is not initialised"
+        );
+        let seeked = consumer.seek(&message.topic, message.partition, Offset::Offset(message.offset), SEEK_TIMEOUT);
+        if let Err(e) = seeked {
+            println!(
+                "{}: Failed to seek back to offset {} for {}/{}: {:?}",
+                self.repr(), message.offset, message.topic, message.partition, e
+            )
+        }
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        // commit past records that can never be triggered
+        self.task_done(message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Error, Publisher, RawMessage};
+
+    use super::{Kafka, KafkaMsg};
+    use rdkafka::config::ClientConfig;
+    use rdkafka::consumer::Consumer;
+    use rdkafka::producer::{FutureProducer, FutureRecord};
+    use rdkafka::Offset;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_commit_list_is_next_offset() {
+        let msg = KafkaMsg {
+            topic: "events".to_string(), partition: 2, offset: 41, msg: String::new()
+        };
+        let tpl = msg.commit_list();
+        let elem = tpl.find_partition("events", 2).expect("Partition should be in the list");
+        assert_eq!(elem.offset(), Offset::Offset(42));
+    }
+
+    // Requires a local single-node broker, eg. `make start-kafka`
+    #[tokio::test]
+    #[ignore]
+    async fn test_e2e_local_broker() {
+        let servers = std::env::var("KAFKA_BOOTSTRAP_SERVERS")
+            .unwrap_or("127.0.0.1:9092".to_string());
+        let suffix: u32 = rand::random();
+        let topic = format!("prefect-event-router-test-{}", suffix);
+        let data = json!({"flow_name": "Test Flow", "deployment_name": "test"}).to_string();
+
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &servers)
+            .create()
+            .unwrap();
+        producer.send(
+            FutureRecord::<(), String>::to(&topic).payload(&data),
+            Duration::from_secs(5)
+        ).await.expect("Failed to produce test record");
+
+        let mut kafka = Kafka {
+            bootstrap_servers: servers,
+            group_id: format!("{}-group", &topic),
+            topics: vec![topic.clone()],
+            client_config: Some([("auto.offset.reset".to_string(), "earliest".to_string())].into()),
+            retry_delay_ms: Some(10),
+            consumer: None
+        };
+        kafka.init().await;
+        let msg = timeout(Duration::from_secs(30), async {
+            loop {
+                if let Some(msg) = kafka.next_message().await {
+                    return msg
+                }
+            }
+        }).await.expect("Did not consume the test record in time");
+        assert_eq!(msg.get_content_str(), data);
+        let failed_offset = msg.offset;
+        kafka.task_failed(msg, &Error::PrefectApiError("Prefect is down".to_string())).await;
+        let msg = timeout(Duration::from_secs(30), async {
+            loop {
+                if let Some(msg) = kafka.next_message().await {
+                    return msg
+                }
+            }
+        }).await.expect("Did not consume the failed record again in time");
+        assert_eq!(msg.offset, failed_offset);
+        let partition = msg.partition;
+        let next_offset = msg.offset + 1;
+        kafka.task_done(msg).await;
+
+        let consumer = kafka.consumer.as_ref().unwrap();
+        let committed = timeout(Duration::from_secs(10), async {
+            loop {
+                let tpl = consumer.committed(Duration::from_secs(5)).unwrap();
+                let offset = tpl.find_partition(&topic, partition).unwrap().offset();
+                if offset == Offset::Offset(next_offset) {
+                    return offset
+                }
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        }).await.expect("Offset was not committed");
+        assert_eq!(committed, Offset::Offset(next_offset));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use crate::routing::Route;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, Publish, QoS, SubscribeFilter, Transport};
+
+const DEFAULT_CLIENT_ID_PREFIX: &str = "prefect-event-router";
+const DEFAULT_QOS: u8 = 1;
+const RECONNECT_INTERVAL_MS: u64 = 1000;
+const DEFAULT_RETRY_DELAY_MS: u64 = 5000;
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttTls {
+    /// PEM file of the CA that signed the broker certificate. The platform roots are used if not set
+    pub ca_file: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Mqtt {
+    pub host: String,
+    pub port: u16,
+    /// Required unless `clean_session` is true, as the broker finds the session by it
+    pub client_id: Option<String>,
+    /// Topic filters to subscribe to, wildcards are allowed
+    pub topics: Vec<String>,
+    pub qos: Option<u8>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: Option<MqttTls>,
+    /// Defaults to false so the broker keeps unacknowledged messages across reconnects
+    pub clean_session: Option<bool>,
+    /// Wait before reconnecting to have the messages of a failed trigger redelivered
+    pub retry_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(skip_serializing, skip_deserializing)]
+    connection: Option<Box<(AsyncClient, EventLoop)>>
+}
+// the event loop cannot be shared between threads so a clone only carries the config
+impl Clone for Mqtt {
+    fn clone(&self) -> Self {
+        Self {
+            host: self.host.clone(),
+            port: self.port,
+            client_id: self.client_id.clone(),
+            topics: self.topics.clone(),
+            qos: self.qos,
+            username: self.username.clone(),
+            password: self.password.clone(),
+            tls: self.tls.clone(),
+            clean_session: self.clean_session,
+            retry_delay_ms: self.retry_delay_ms,
+            routes: self.routes.clone(),
+            connection: None
+        }
+    }
+}
+impl fmt::Debug for Mqtt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mqtt")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("client_id", &self.client_id)
+            .field("topics", &self.topics)
+            .field("qos", &self.qos)
+            .field("username", &self.username)
+            .field("tls", &self.tls)
+            .field("clean_session", &self.clean_session)
+            .field("retry_delay_ms", &self.retry_delay_ms)
+            .field("routes", &self.routes)
+            .field("connected", &self.connection.is_some())
+            .finish()
+    }
+}
+
+pub struct MqttMsg {
+    msg: String,
+    publish: Publish
+}
+impl MqttMsg {
+    fn new(publish: Publish) -> Self {
+        Self {
+            msg: String::from_utf8_lossy(&publish.payload).into_owned(),
+            publish
+        }
+    }
+}
+impl RawMessage for MqttMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::from([("topic".to_string(), self.publish.topic.clone())])
+    }
+}
+
+impl Mqtt {
+    /// A persistent session must be resumed under the same id after a restart, and two
+    /// routers sharing one would keep disconnecting each other, so only clean sessions
+    /// get a generated id
+    fn client_id(&self) -> Result<String, String> {
+        match (&self.client_id, self.clean_session.unwrap_or(false)) {
+            (Some(client_id), _) => Ok(client_id.clone()),
+            (None, true) => {
+                let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+                Ok(format!("{}-{}-{}", DEFAULT_CLIENT_ID_PREFIX, std::process::id(), nanos))
+            },
+            (None, false) => Err("MQTT client_id must be set unless clean_session is true".to_string())
+        }
+    }
+    pub fn validate(&self) -> Result<(), String> {
+        self.client_id()?;
+        if self.qos.is_some_and(|qos| qos > 2) {
+            return Err("MQTT qos must be 0, 1 or 2".to_string())
+        }
+        Ok(())
+    }
+    fn mqtt_options(&self) -> MqttOptions {
+        let client_id = self.client_id().expect("MQTT client_id is checked when the config is loaded");
+        let mut options = MqttOptions::new(client_id, &self.host, self.port);
+        options
+            .set_manual_acks(true)
+            .set_clean_session(self.clean_session.unwrap_or(false));
+        if let Some(username) = &self.username {
+            options.set_credentials(username, self.password.clone().unwrap_or_default());
+        }
+        if let Some(tls) = &self.tls {
+            let transport = match &tls.ca_file {
+                Some(path) => Transport::tls(
+                    std::fs::read(path).expect("Unable to read the MQTT CA file"), None, None
+                ),
+                None => Transport::tls_with_default_config()
+            };
+            options.set_transport(transport);
+        }
+        options
+    }
+    fn subscribe_filters(&self) -> Vec<SubscribeFilter> {
+        let qos = rumqttc::qos(self.qos.unwrap_or(DEFAULT_QOS)).expect("MQTT qos must be 0, 1 or 2");
+        self.topics.iter().map(|t| SubscribeFilter::new(t.clone(), qos)).collect()
+    }
+    /// Disconnects once pending acks have been sent and starts a new connection. The broker
+    /// then redelivers the unacknowledged messages of the resumed session
+    async fn reconnect(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            let (client, mut eventloop) = *connection;
+            if client.disconnect().await.is_ok() {
+                let _ = tokio::time::timeout(DISCONNECT_TIMEOUT, async {
+                    loop {
+                        match eventloop.poll().await {
+                            Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+                            _ => {}
+                        }
+                    }
+                }).await;
+            }
+        }
+        self.init().await
+    }
+}
+
+#[async_trait]
+impl Publisher for Mqtt {
+    type PubMessage = MqttMsg;
+
+    fn repr(&self) -> String {
+        format!("MQTT {}:{}/{}", self.host, self.port, self.topics.join(","))
+    }
+    async fn init(&mut self) {
+        // subscriptions are made once the broker acknowledges the connection
+        self.connection = Some(Box::new(AsyncClient::new(self.mqtt_options(), 10)));
+    }
+    async fn next_message(&mut self) -> Option<MqttMsg> {
+        let eventloop = &mut self.connection.as_mut().expect(
+            "Cannot await messages without the MQTT client being initialised"
+        ).1;
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::Publish(publish))) => Some(MqttMsg::new(publish)),
+            Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                if !ack.session_present {
+                    let client = self.connection.as_ref().unwrap().0.clone();
+                    if let Err(e) = client.subscribe_many(self.subscribe_filters()).await {
+                        println!("{}: Unable to subscribe: {:?}", self.repr(), e)
+                    }
+                }
+                None
+            },
+            Ok(_) => None,
+            Err(e) => {
+                // polling again reconnects
+                println!("{}: Connection error {:?}", self.repr(), e);
+                tokio::time::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS)).await;
+                None
+            }
+        }
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        let client = self.connection.as_ref().expect(
+            "Cannot call task done on a message when the MQTT client is not initialised"
+        ).0.clone();
+        if let Err(e) = client.ack(&message.publish).await {
+            println!("{}: Failed to ack message: {:?}", self.repr(), e)
+        }
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, _error: &Error) {
+        // the broker does not redeliver an unacknowledged message on a live connection,
+        // only once the session is resumed
+        if message.publish.qos == QoS::AtMostOnce || self.clean_session.unwrap_or(false) {
+            return
+        }
+        tokio::time::sleep(Duration::from_millis(self.retry_delay_ms.unwrap_or(DEFAULT_RETRY_DELAY_MS))).await;
+        println!("{}: Reconnecting to have message on {} redelivered", self.repr(), message.publish.topic);
+        self.reconnect().await
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        // redelivery would not make the message valid so release it from the session
+        self.task_done(message).await
+    }
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::RawMessage;
+
+    use super::{Mqtt, MqttMsg};
+    use rumqttc::{Publish, QoS};
+    use serde_json::json;
+
+    #[test]
+    fn test_topic_is_an_attribute() {
+        let msg = MqttMsg::new(Publish::new("sensors/a/temp", QoS::AtLeastOnce, "{\"temp\": 21}"));
+        assert_eq!(msg.get_content_str(), "{\"temp\": 21}");
+        assert_eq!(msg.get_attributes()["topic"], "sensors/a/temp");
+    }
+
+    #[test]
+    fn test_client_id_for_persistent_sessions() {
+        let config = |extra: serde_json::Value| {
+            let mut config = json!({"host": "127.0.0.1", "port": 1883, "topics": ["sensors/#"]});
+            config.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            serde_json::from_value::<Mqtt>(config).unwrap()
+        };
+        assert!(config(json!({})).client_id().is_err());
+        assert_eq!(config(json!({"client_id": "router-1"})).client_id().unwrap(), "router-1");
+        let clean = config(json!({"clean_session": true}));
+        assert!(clean.client_id().unwrap().starts_with("prefect-event-router-"));
+    }
+}
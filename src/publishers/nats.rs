@@ -0,0 +1,240 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use futures::StreamExt;
+use async_nats::jetstream::{self, consumer::pull, AckKind};
+
+const DEFAULT_NAK_DELAY_MS: u64 = 5000;
+const RECONNECT_INTERVAL_MS: u64 = 1000;
+
+/// Durable JetStream pull consumer to bind to. It is created on the stream if it does not exist yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JetStreamConsumer {
+    pub stream: String,
+    pub durable_name: String
+}
+
+enum NatsSubscription {
+    Core(async_nats::Subscriber),
+    JetStream(Box<pull::Stream>)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Nats {
+    pub server_url: String,
+    pub subject: String,
+    /// Consume through a JetStream durable consumer instead of a core NATS subscription
+    pub jetstream: Option<JetStreamConsumer>,
+    /// Time JetStream waits before redelivering a message whose trigger failed
+    pub nak_delay_ms: Option<u64>,
+    #[serde(skip_serializing, skip_deserializing)]
+    subscription: Option<NatsSubscription>
+}
+// the subscription cannot be shared between threads so a clone only carries the config
+impl Clone for Nats {
+    fn clone(&self) -> Self {
+        Self {
+            server_url: self.server_url.clone(),
+            subject: self.subject.clone(),
+            jetstream: self.jetstream.clone(),
+            nak_delay_ms: self.nak_delay_ms,
+            subscription: None
+        }
+    }
+}
+impl fmt::Debug for Nats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Nats")
+            .field("server_url", &self.server_url)
+            .field("subject", &self.subject)
+            .field("jetstream", &self.jetstream)
+            .field("nak_delay_ms", &self.nak_delay_ms)
+            .field("connected", &self.subscription.is_some())
+            .finish()
+    }
+}
+
+pub struct NatsMsg {
+    msg: String,
+    // only set for messages that need acknowledging
+    jetstream_message: Option<jetstream::Message>
+}
+impl NatsMsg {
+    fn from_core(message: async_nats::Message) -> Self {
+        Self {
+            msg: String::from_utf8_lossy(&message.payload).into_owned(),
+            jetstream_message: None
+        }
+    }
+    fn from_jetstream(message: jetstream::Message) -> Self {
+        Self {
+            msg: String::from_utf8_lossy(&message.payload).into_owned(),
+            jetstream_message: Some(message)
+        }
+    }
+}
+impl RawMessage for NatsMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+}
+
+impl Nats {
+    async fn subscribe(&self) -> Result<NatsSubscription, String> {
+        let client = async_nats::connect(&self.server_url).await.map_err(|e| e.to_string())?;
+        match &self.jetstream {
+            None => {
+                let subscriber = client.subscribe(self.subject.clone()).await
+                    .map_err(|e| e.to_string())?;
+                Ok(NatsSubscription::Core(subscriber))
+            },
+            Some(js_config) => {
+                let stream = jetstream::new(client).get_stream(&js_config.stream).await
+                    .map_err(|e| e.to_string())?;
+                let consumer: jetstream::consumer::PullConsumer = stream.get_or_create_consumer(
+                    &js_config.durable_name,
+                    pull::Config {
+                        durable_name: Some(js_config.durable_name.clone()),
+                        filter_subject: self.subject.clone(),
+                        ..Default::default()
+                    }
+                ).await.map_err(|e| e.to_string())?;
+                let messages = consumer.messages().await.map_err(|e| e.to_string())?;
+                Ok(NatsSubscription::JetStream(Box::new(messages)))
+            }
+        }
+    }
+    async fn connect(&mut self) {
+        self.subscription = match self.subscribe().await {
+            Ok(subscription) => Some(subscription),
+            Err(e) => {
+                println!("{}: Unable to subscribe: {}", self.repr(), e);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for Nats {
+    type PubMessage = NatsMsg;
+
+    fn repr(&self) -> String {
+        match &self.jetstream {
+            Some(js_config) => format!("NATS {}/{}", js_config.stream, js_config.durable_name),
+            None => format!("NATS {}", self.subject)
+        }
+    }
+    async fn init(&mut self) {
+        self.connect().await
+    }
+    async fn next_message(&mut self) -> Option<NatsMsg> {
+        let next = match self.subscription.as_mut() {
+            Some(NatsSubscription::Core(subscriber)) => subscriber.next().await
+                .map(|m| Ok(NatsMsg::from_core(m))),
+            Some(NatsSubscription::JetStream(messages)) => messages.next().await
+                .map(|r| r.map(NatsMsg::from_jetstream).map_err(|e| e.to_string())),
+            None => {
+                tokio::time::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS)).await;
+                println!("{}: Reconnecting", self.repr());
+                self.connect().await;
+                return None
+            }
+        };
+        match next {
+            Some(Ok(msg)) => Some(msg),
+            Some(Err(e)) => {
+                println!("{}: Got error receiving message: {}", self.repr(), e);
+                None
+            },
+            None => {
+                println!("{}: Subscription closed", self.repr());
+                self.subscription = None;
+                None
+            }
+        }
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        if let Some(js_message) = message.jetstream_message {
+            if let Err(e) = js_message.ack().await {
+                println!("{}: Failed to ack message: {}", self.repr(), e)
+            }
+        }
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, _error: &Error) {
+        if let Some(js_message) = message.jetstream_message {
+            let delay = Duration::from_millis(self.nak_delay_ms.unwrap_or(DEFAULT_NAK_DELAY_MS));
+            if let Err(e) = js_message.ack_with(AckKind::Nak(Some(delay))).await {
+                println!("{}: Failed to nak message: {}", self.repr(), e)
+            }
+        }
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        if let Some(js_message) = message.jetstream_message {
+            if let Err(e) = js_message.ack_with(AckKind::Term).await {
+                println!("{}: Failed to terminate message: {}", self.repr(), e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Error, Publisher, RawMessage};
+
+    use super::{JetStreamConsumer, Nats};
+    use async_nats::jetstream;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    async fn next_with_timeout(nats: &mut Nats) -> super::NatsMsg {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(msg) = nats.next_message().await {
+                    return msg
+                }
+            }
+        }).await.expect("Did not receive a message in time")
+    }
+
+    // Requires a local nats-server with JetStream enabled, eg. `make start-nats`
+    #[tokio::test]
+    #[ignore]
+    async fn test_jetstream_nak_redelivers() {
+        let server_url = std::env::var("NATS_URL").unwrap_or("nats://127.0.0.1:4222".to_string());
+        let suffix: u32 = rand::random();
+        let stream_name = format!("ROUTER_TEST_{}", suffix);
+        let subject = format!("router.test.{}", suffix);
+        let client = async_nats::connect(&server_url).await.unwrap();
+        let context = jetstream::new(client);
+        context.create_stream(jetstream::stream::Config {
+            name: stream_name.clone(),
+            subjects: vec![subject.clone()],
+            ..Default::default()
+        }).await.unwrap();
+        let data = json!({"flow_name": "Test Flow", "deployment_name": "test"}).to_string();
+        context.publish(subject.clone(), data.clone().into()).await.unwrap().await.unwrap();
+
+        let mut nats = Nats {
+            server_url,
+            subject,
+            jetstream: Some(JetStreamConsumer {
+                stream: stream_name.clone(), durable_name: "router".to_string()
+            }),
+            nak_delay_ms: Some(10),
+            subscription: None
+        };
+        nats.init().await;
+        let first = next_with_timeout(&mut nats).await;
+        assert_eq!(first.get_content_str(), data);
+        nats.task_failed(first, &Error::PrefectApiError("test".to_string())).await;
+
+        let redelivered = next_with_timeout(&mut nats).await;
+        assert_eq!(redelivered.get_content_str(), data);
+        nats.task_done(redelivered).await;
+        context.delete_stream(&stream_name).await.unwrap();
+    }
+}
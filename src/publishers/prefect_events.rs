@@ -0,0 +1,375 @@
+use crate::interfaces::{Publisher, RawMessage};
+use crate::routing::Route;
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+const INITIAL_BACKOFF_MS: u64 = 1000;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 60000;
+// ids of the latest events, to skip the ones the server sends again after a reconnect
+const RECENT_IDS: usize = 1000;
+
+type Connection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Serialize, Deserialize)]
+pub struct PrefectEvents {
+    /// Defaults to the `PREFECT_API_URL` env var
+    pub api_url: Option<String>,
+    /// An event filter as accepted by the Prefect API,
+    /// eg. `{"event": {"name": ["prefect.flow-run.Completed"]}}`
+    #[serde(default)]
+    pub filter: serde_json::Map<String, serde_json::Value>,
+    /// Upper bound of the delay between reconnect attempts
+    pub max_backoff_ms: Option<u64>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(skip_serializing, skip_deserializing)]
+    connection: Option<Connection>,
+    #[serde(skip_serializing, skip_deserializing)]
+    backoff_ms: u64,
+    /// `occurred` of the latest event, the stream is resumed from it after a reconnect
+    #[serde(skip_serializing, skip_deserializing)]
+    since: Option<String>,
+    #[serde(skip_serializing, skip_deserializing)]
+    recent_ids: VecDeque<String>
+}
+// the connection cannot be shared between threads so a clone only carries the config
+impl Clone for PrefectEvents {
+    fn clone(&self) -> Self {
+        Self {
+            api_url: self.api_url.clone(),
+            filter: self.filter.clone(),
+            max_backoff_ms: self.max_backoff_ms,
+            routes: self.routes.clone(),
+            connection: None,
+            backoff_ms: INITIAL_BACKOFF_MS,
+            since: None,
+            recent_ids: VecDeque::new()
+        }
+    }
+}
+impl fmt::Debug for PrefectEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrefectEvents")
+            .field("api_url", &self.api_url)
+            .field("filter", &self.filter)
+            .field("max_backoff_ms", &self.max_backoff_ms)
+            .field("routes", &self.routes)
+            .field("since", &self.since)
+            .field("connected", &self.connection.is_some())
+            .finish()
+    }
+}
+
+/// A Prefect event, passed on as the `event` parameter of the triggered flow
+pub struct PrefectEventMsg {
+    event: serde_json::Value
+}
+impl RawMessage for PrefectEventMsg {
+    fn get_content_str(&self) -> String {
+        json!({"event": &self.event}).to_string()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        event_attributes(&self.event)
+    }
+}
+
+/// The event name, its resource and the name of each related resource by role,
+/// eg. `deployment` or `flow`
+fn event_attributes(event: &serde_json::Value) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut insert = |key: &str, value: &serde_json::Value| {
+        if let Some(value) = value.as_str() {
+            attributes.insert(key.to_string(), value.to_string());
+        }
+    };
+    insert("event", &event["event"]);
+    insert("resource_id", &event["resource"]["prefect.resource.id"]);
+    insert("resource_name", &event["resource"]["prefect.resource.name"]);
+    insert("state", &event["resource"]["prefect.state-name"]);
+    for related in event["related"].as_array().into_iter().flatten() {
+        if let Some(role) = related["prefect.resource.role"].as_str() {
+            insert(role, &related["prefect.resource.name"]);
+        }
+    }
+    attributes
+}
+
+/// `http(s)://host/api` -> `ws(s)://host/api/events/out`
+fn events_url(api_url: &str) -> String {
+    let url = match api_url.strip_prefix("http") {
+        Some(rest) => format!("ws{}", rest),
+        None => api_url.to_string()
+    };
+    format!("{}/events/out", url.trim_end_matches('/'))
+}
+
+/// Connects, authenticates and sends the filter, as done by the Prefect client
+async fn connect(
+    url: String, token: Option<String>, filter: serde_json::Map<String, serde_json::Value>
+) -> Result<Connection, String> {
+    let (mut connection, _) = connect_async(url).await.map_err(|e| e.to_string())?;
+    let auth = json!({"type": "auth", "token": token}).to_string();
+    connection.send(Message::Text(auth)).await.map_err(|e| e.to_string())?;
+    let reply = match connection.next().await {
+        Some(Ok(Message::Text(reply))) => reply,
+        other => return Err(format!("Expected an auth reply, got {:?}", other))
+    };
+    let reply: serde_json::Value = serde_json::from_str(&reply).map_err(|e| e.to_string())?;
+    if reply["type"] != "auth_success" {
+        return Err(format!("Authentication failed: {}", reply))
+    }
+    let filter = json!({"type": "filter", "filter": filter}).to_string();
+    connection.send(Message::Text(filter)).await.map_err(|e| e.to_string())?;
+    Ok(connection)
+}
+
+impl PrefectEvents {
+    async fn connect(&mut self) -> Result<(), String> {
+        let url = match &self.api_url {
+            Some(api_url) => events_url(api_url),
+            None => return Err("Env var PREFECT_API_URL is required when api_url is not set".to_string())
+        };
+        let mut filter = self.filter.clone();
+        if let Some(since) = &self.since {
+            // the server sends the events since this time before the live ones
+            let occurred = filter.entry("occurred").or_insert(json!({}));
+            occurred["since"] = json!(since);
+        }
+        let token = std::env::var("PREFECT_API_KEY").ok();
+        self.connection = Some(connect(url, token, filter).await?);
+        Ok(())
+    }
+    /// Waits before the next connect attempt, doubling the delay each time up to `max_backoff_ms`
+    async fn backoff(&mut self) {
+        let backoff_ms = self.backoff_ms.max(INITIAL_BACKOFF_MS);
+        tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+        self.backoff_ms = (backoff_ms * 2).min(self.max_backoff_ms.unwrap_or(DEFAULT_MAX_BACKOFF_MS));
+    }
+    /// Records the event, returning false if it was already received
+    fn is_new(&mut self, event: &serde_json::Value) -> bool {
+        if let Some(occurred) = event["occurred"].as_str() {
+            self.since = Some(occurred.to_string());
+        }
+        let id = match event["id"].as_str() {
+            Some(id) => id.to_string(),
+            None => return true
+        };
+        if self.recent_ids.contains(&id) {
+            return false
+        }
+        if self.recent_ids.len() == RECENT_IDS {
+            self.recent_ids.pop_front();
+        }
+        self.recent_ids.push_back(id);
+        true
+    }
+}
+
+#[async_trait]
+impl Publisher for PrefectEvents {
+    type PubMessage = PrefectEventMsg;
+
+    fn repr(&self) -> String {
+        match &self.api_url {
+            Some(api_url) => format!("Prefect events {}", events_url(api_url)),
+            None => "Prefect events".to_string()
+        }
+    }
+    async fn init(&mut self) {
+        if self.api_url.is_none() {
+            self.api_url = std::env::var("PREFECT_API_URL").ok();
+        }
+        // only events from now on, unless the filter asks for earlier ones
+        if self.filter.get("occurred").and_then(|occurred| occurred.get("since")).is_none() {
+            self.since = Some(Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true));
+        }
+        // a server that is down at startup is retried by next_message, like a dropped connection
+        if let Err(e) = self.connect().await {
+            println!("{}: Failed to subscribe: {}", self.repr(), e)
+        }
+    }
+    async fn next_message(&mut self) -> Option<PrefectEventMsg> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => {
+                self.backoff().await;
+                if let Err(e) = self.connect().await {
+                    println!("{}: Failed to reconnect: {}", self.repr(), e);
+                }
+                return None
+            }
+        };
+        let message = connection.next().await;
+        match message {
+            Some(Ok(Message::Text(msg))) => {
+                self.connection = Some(connection);
+                self.backoff_ms = INITIAL_BACKOFF_MS;
+                let mut msg: serde_json::Value = match serde_json::from_str(&msg) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        println!("{}: Skipping a frame that is not JSON: {}", self.repr(), e);
+                        return None
+                    }
+                };
+                let event = msg["event"].take();
+                if msg["type"] != "event" || !self.is_new(&event) {
+                    return None
+                }
+                Some(PrefectEventMsg {event})
+            },
+            Some(Ok(Message::Close(frame))) => {
+                println!("{}: Closed by the server: {:?}", self.repr(), frame);
+                None
+            },
+            Some(Ok(_)) => {
+                self.connection = Some(connection);
+                None
+            },
+            Some(Err(e)) => {
+                println!("{}: Connection lost: {}", self.repr(), e);
+                None
+            },
+            None => {
+                println!("{}: Connection lost", self.repr());
+                None
+            }
+        }
+    }
+    async fn task_done(&mut self, _message: Self::PubMessage) {}
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Publisher, RawMessage};
+    use crate::routing::to_q_message;
+
+    use super::{events_url, PrefectEvents};
+    use futures::{SinkExt, StreamExt};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn completed_event(id: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "occurred": "2024-03-01T10:15:00.000000Z",
+            "event": "prefect.flow-run.Completed",
+            "resource": {
+                "prefect.resource.id": "prefect.flow-run.1234",
+                "prefect.resource.name": "brave-fox",
+                "prefect.state-name": "Completed"
+            },
+            "related": [
+                {"prefect.resource.id": "prefect.flow.1", "prefect.resource.role": "flow", "prefect.resource.name": "extract"},
+                {"prefect.resource.id": "prefect.deployment.2", "prefect.resource.role": "deployment", "prefect.resource.name": "nightly"}
+            ]
+        })
+    }
+
+    #[test]
+    fn test_events_url() {
+        assert_eq!(events_url("http://127.0.0.1:4200/api"), "ws://127.0.0.1:4200/api/events/out");
+        assert_eq!(events_url("https://prefect.example.com/api/"), "wss://prefect.example.com/api/events/out");
+    }
+
+    #[tokio::test]
+    async fn test_retries_without_api_url_instead_of_panicking() {
+        let mut events: PrefectEvents = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(events.repr(), "Prefect events");
+        let error = events.connect().await.expect_err("Should not connect without an API url");
+        assert!(error.contains("PREFECT_API_URL"));
+        assert!(events.connection.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscribes_and_routes_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}/api", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = accept_async(stream).await.unwrap();
+            let auth = connection.next().await.unwrap().unwrap().into_text().unwrap();
+            connection.send(Message::Text(json!({"type": "auth_success"}).to_string())).await.unwrap();
+            let filter = connection.next().await.unwrap().unwrap().into_text().unwrap();
+            for id in ["a", "a", "b"] {
+                let event = json!({"type": "event", "event": completed_event(id)}).to_string();
+                connection.send(Message::Text(event)).await.unwrap();
+            }
+            (auth, filter)
+        });
+
+        let mut events: PrefectEvents = serde_json::from_value(json!(
+            {
+                "api_url": api_url,
+                "filter": {"event": {"name": ["prefect.flow-run.Completed"]}},
+                "routes": [{"match": {"deployment": "nightly", "state": "Completed"}, "flow_name": "Load", "deployment_name": "after-extract"}]
+            }
+        )).unwrap();
+        events.init().await;
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            if let Some(msg) = events.next_message().await {
+                received.push(msg)
+            }
+        }
+
+        let (auth, filter) = server.await.unwrap();
+        let auth: serde_json::Value = serde_json::from_str(&auth).unwrap();
+        assert_eq!(auth["type"], "auth");
+        let filter: serde_json::Value = serde_json::from_str(&filter).unwrap();
+        assert_eq!(filter["filter"]["event"]["name"][0], "prefect.flow-run.Completed");
+        assert!(filter["filter"]["occurred"]["since"].is_string(), "Should only ask for new events");
+
+        let ids: Vec<String> = received.iter().map(|msg| msg.event["id"].as_str().unwrap().to_string()).collect();
+        assert_eq!(ids, vec!["a", "b"], "Repeated events should be skipped");
+        let attributes = received[0].get_attributes();
+        assert_eq!(attributes["flow"], "extract");
+        assert_eq!(attributes["resource_name"], "brave-fox");
+        let q_message = to_q_message(events.routes(), &received[0]).unwrap();
+        assert_eq!(q_message.get_flow_deployment(), ("Load".to_string(), "after-extract".to_string()));
+        assert_eq!(q_message.get_flow_parameters().as_ref().unwrap()["event"]["event"], "prefect.flow-run.Completed");
+    }
+
+    #[tokio::test]
+    async fn test_subscribes_once_the_server_is_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let mut events: PrefectEvents = serde_json::from_value(json!(
+            {"api_url": format!("http://{}/api", addr), "filter": {}}
+        )).unwrap();
+        events.init().await;
+        assert!(events.connection.is_none());
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = accept_async(stream).await.unwrap();
+            connection.next().await;
+            connection.send(Message::Text(json!({"type": "auth_success"}).to_string())).await.unwrap();
+            connection.next().await;
+            let event = json!({"type": "event", "event": completed_event("a")}).to_string();
+            connection.send(Message::Text(event)).await.unwrap();
+            connection.next().await;
+        });
+        let msg = loop {
+            if let Some(msg) = events.next_message().await {
+                break msg
+            }
+        };
+        assert_eq!(msg.event["id"], "a");
+    }
+}
@@ -0,0 +1,313 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use redis::aio::ConnectionManager;
+use redis::streams::{
+    StreamAutoClaimOptions, StreamAutoClaimReply, StreamId, StreamReadOptions, StreamReadReply
+};
+use redis::{AsyncCommands, RedisResult, Value};
+
+const DEFAULT_FIELD: &str = "data";
+const DEFAULT_BLOCK_MS: usize = 5000;
+const DEFAULT_COUNT: usize = 10;
+const DEFAULT_CLAIM_IDLE_MS: u64 = 60000;
+const DEFAULT_CLAIM_INTERVAL_MS: u64 = 30000;
+const CLAIM_START: &str = "0-0";
+const INITIAL_BACKOFF_MS: u64 = 1000;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 60000;
+
+#[derive(Serialize, Deserialize)]
+pub struct RedisStream {
+    pub url: String,
+    pub streams: Vec<String>,
+    pub group: String,
+    pub consumer: String,
+    /// Field of the stream entry holding the message content. Defaults to `data`
+    pub field: Option<String>,
+    pub block_ms: Option<usize>,
+    pub count: Option<usize>,
+    /// Pending entries idle for longer than this are claimed from other consumers
+    pub claim_idle_ms: Option<u64>,
+    /// How often to look for stale pending entries
+    pub claim_interval_ms: Option<u64>,
+    /// Upper bound of the wait between connect attempts while Redis is unavailable
+    pub max_backoff_ms: Option<u64>,
+    #[serde(skip_serializing, skip_deserializing)]
+    connection: Option<ConnectionManager>,
+    #[serde(skip_serializing, skip_deserializing)]
+    messages: Vec<RedisStreamMsg>,
+    #[serde(skip_serializing, skip_deserializing)]
+    last_claim: Option<Instant>,
+    /// Where the next XAUTOCLAIM of each stream carries on from
+    #[serde(skip_serializing, skip_deserializing)]
+    claim_cursors: HashMap<String, String>,
+    #[serde(skip_serializing, skip_deserializing)]
+    backoff_ms: u64
+}
+// the connection cannot be shared between threads so a clone only carries the config
+impl Clone for RedisStream {
+    fn clone(&self) -> Self {
+        Self {
+            url: self.url.clone(),
+            streams: self.streams.clone(),
+            group: self.group.clone(),
+            consumer: self.consumer.clone(),
+            field: self.field.clone(),
+            block_ms: self.block_ms,
+            count: self.count,
+            claim_idle_ms: self.claim_idle_ms,
+            claim_interval_ms: self.claim_interval_ms,
+            max_backoff_ms: self.max_backoff_ms,
+            connection: None,
+            messages: Vec::new(),
+            last_claim: None,
+            claim_cursors: HashMap::new(),
+            backoff_ms: INITIAL_BACKOFF_MS
+        }
+    }
+}
+impl fmt::Debug for RedisStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStream")
+            .field("url", &self.url)
+            .field("streams", &self.streams)
+            .field("group", &self.group)
+            .field("consumer", &self.consumer)
+            .field("field", &self.field)
+            .field("block_ms", &self.block_ms)
+            .field("count", &self.count)
+            .field("claim_idle_ms", &self.claim_idle_ms)
+            .field("claim_interval_ms", &self.claim_interval_ms)
+            .field("max_backoff_ms", &self.max_backoff_ms)
+            .field("connected", &self.connection.is_some())
+            .finish()
+    }
+}
+
+pub struct RedisStreamMsg {
+    stream: String,
+    id: String,
+    msg: String
+}
+impl RedisStreamMsg {
+    fn from_entry(stream: &str, entry: &StreamId, field: &str) -> Self {
+        let msg = match entry.map.get(field) {
+            Some(Value::BulkString(bytes)) => String::from_utf8_lossy(bytes).into_owned(),
+            Some(Value::SimpleString(s)) => s.clone(),
+            _ => String::new()
+        };
+        Self {stream: stream.to_string(), id: entry.id.clone(), msg}
+    }
+}
+impl RawMessage for RedisStreamMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+}
+
+impl RedisStream {
+    /// Connects and creates the consumer group on every stream that does not have it yet
+    async fn connect(&mut self) -> Result<(), String> {
+        let client = redis::Client::open(self.url.as_str()).map_err(|e| e.to_string())?;
+        let mut connection = ConnectionManager::new(client).await.map_err(|e| e.to_string())?;
+        for stream in &self.streams {
+            let created: RedisResult<()> = connection.xgroup_create_mkstream(
+                stream, &self.group, "$"
+            ).await;
+            if let Err(e) = created {
+                if e.code() != Some("BUSYGROUP") {
+                    return Err(format!("Unable to create consumer group {} on {}: {:?}", &self.group, stream, e))
+                }
+            }
+        }
+        self.connection = Some(connection);
+        Ok(())
+    }
+    /// Waits before the next connect attempt, doubling the delay each time up to `max_backoff_ms`
+    async fn backoff(&mut self) {
+        let backoff_ms = self.backoff_ms.max(INITIAL_BACKOFF_MS);
+        tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+        self.backoff_ms = (backoff_ms * 2).min(self.max_backoff_ms.unwrap_or(DEFAULT_MAX_BACKOFF_MS));
+    }
+    fn connection(&self) -> ConnectionManager {
+        self.connection.as_ref().expect(
+            "Cannot use the stream without the Redis connection being initialised"
+        ).clone()
+    }
+    fn field(&self) -> &str {
+        self.field.as_deref().unwrap_or(DEFAULT_FIELD)
+    }
+    fn claim_due(&self) -> bool {
+        // a scan that stopped at `count` entries carries on straight away
+        if self.claim_cursors.values().any(|cursor| cursor != CLAIM_START) {
+            return true
+        }
+        let interval = Duration::from_millis(self.claim_interval_ms.unwrap_or(DEFAULT_CLAIM_INTERVAL_MS));
+        match self.last_claim {
+            Some(last) => last.elapsed() >= interval,
+            None => true
+        }
+    }
+    /// Takes over entries that were delivered to a consumer but never acknowledged,
+    /// eg. because that router instance crashed mid-trigger
+    async fn claim_stale(&mut self) -> RedisResult<()> {
+        let mut connection = self.connection();
+        let idle = self.claim_idle_ms.unwrap_or(DEFAULT_CLAIM_IDLE_MS);
+        let mut claimed = Vec::new();
+        for stream in &self.streams {
+            let options = StreamAutoClaimOptions::default().count(self.count.unwrap_or(DEFAULT_COUNT));
+            let start = self.claim_cursors.get(stream).map(|c| c.as_str()).unwrap_or(CLAIM_START);
+            let reply: StreamAutoClaimReply = connection.xautoclaim_options(
+                stream, &self.group, &self.consumer, idle, start, options
+            ).await?;
+            // Redis returns 0-0 once the whole pending list has been scanned
+            self.claim_cursors.insert(stream.clone(), reply.next_stream_id.clone());
+            // messages are popped from the back so reverse to keep stream order
+            for entry in reply.claimed.iter().rev() {
+                claimed.push(RedisStreamMsg::from_entry(stream, entry, self.field()))
+            }
+        }
+        self.messages.extend(claimed);
+        self.last_claim = Some(Instant::now());
+        Ok(())
+    }
+    async fn read_new(&mut self) -> RedisResult<()> {
+        let mut connection = self.connection();
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .block(self.block_ms.unwrap_or(DEFAULT_BLOCK_MS))
+            .count(self.count.unwrap_or(DEFAULT_COUNT));
+        let ids: Vec<&str> = self.streams.iter().map(|_| ">").collect();
+        let reply: Option<StreamReadReply> = connection.xread_options(
+            &self.streams, &ids, &options
+        ).await?;
+        for key in reply.map(|r| r.keys).unwrap_or_default() {
+            // messages are popped from the back so reverse to keep stream order
+            for entry in key.ids.iter().rev() {
+                self.messages.push(RedisStreamMsg::from_entry(&key.key, entry, self.field()))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Publisher for RedisStream {
+    type PubMessage = RedisStreamMsg;
+
+    fn repr(&self) -> String {
+        format!("Redis {}/{}", self.group, self.streams.join(","))
+    }
+    async fn init(&mut self) {
+        // Redis being down at startup is retried by next_message
+        if let Err(e) = self.connect().await {
+            println!("{}: Failed to connect: {}", self.repr(), e)
+        }
+    }
+    async fn next_message(&mut self) -> Option<RedisStreamMsg> {
+        if self.connection.is_none() {
+            self.backoff().await;
+            match self.connect().await {
+                Ok(_) => self.backoff_ms = INITIAL_BACKOFF_MS,
+                Err(e) => {
+                    println!("{}: Failed to reconnect: {}", self.repr(), e);
+                    return None
+                }
+            }
+        }
+        if self.messages.is_empty() && self.claim_due() {
+            if let Err(e) = self.claim_stale().await {
+                println!("{}: Failed to claim stale entries: {:?}", self.repr(), e)
+            }
+        }
+        if self.messages.is_empty() {
+            if let Err(e) = self.read_new().await {
+                println!("{}: Failed to read from streams: {:?}", self.repr(), e);
+                tokio::time::sleep(Duration::from_millis(1000)).await;
+            }
+        }
+        self.messages.pop()
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        let mut connection = self.connection();
+        let acked: RedisResult<i64> = connection.xack(&message.stream, &self.group, &[&message.id]).await;
+        if let Err(e) = acked {
+            println!("{}: Failed to ack {}/{}: {:?}", self.repr(), &message.stream, &message.id, e)
+        }
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        // acknowledged so it is not claimed again by XAUTOCLAIM
+        self.task_done(message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Publisher, RawMessage};
+
+    use super::RedisStream;
+    use redis::AsyncCommands;
+    use redis::streams::{StreamPendingReply, StreamReadOptions, StreamReadReply};
+    use serde_json::json;
+
+    fn new_redis_stream(url: &str, stream: &str, consumer: &str) -> RedisStream {
+        serde_json::from_value(json!(
+            {
+                "url": url,
+                "streams": [stream],
+                "group": "router",
+                "consumer": consumer,
+                "claim_idle_ms": 0,
+                "block_ms": 100
+            }
+        )).unwrap()
+    }
+
+    #[test]
+    fn test_claim_carries_on_from_cursor() {
+        let mut router = new_redis_stream("redis://127.0.0.1:6379", "events", "router-1");
+        router.last_claim = Some(std::time::Instant::now());
+        assert!(!router.claim_due());
+        router.claim_cursors.insert("events".to_string(), "1700000000000-5".to_string());
+        assert!(router.claim_due());
+        router.claim_cursors.insert("events".to_string(), "0-0".to_string());
+        assert!(!router.claim_due());
+    }
+
+    #[tokio::test]
+    async fn test_retries_connecting_instead_of_panicking() {
+        let mut router = new_redis_stream("not-a-redis-url", "events", "router-1");
+        router.init().await;
+        assert!(router.connection.is_none());
+        assert!(router.next_message().await.is_none());
+        assert!(router.connection.is_none());
+    }
+
+    // Requires a local redis-server, eg. `make start-redis`
+    #[tokio::test]
+    #[ignore]
+    async fn test_claims_entries_of_crashed_consumer() {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".to_string());
+        let suffix: u32 = rand::random();
+        let stream = format!("router-test-{}", suffix);
+        let mut router = new_redis_stream(&url, &stream, "router-1");
+        router.init().await;
+
+        let mut connection = router.connection();
+        let data = json!({"flow_name": "Test Flow", "deployment_name": "test"}).to_string();
+        let _: String = connection.xadd(&stream, "*", &[("data", &data)]).await.unwrap();
+        // read the entry as another consumer that never acknowledges it
+        let options = StreamReadOptions::default().group("router", "crashed").count(1);
+        let _: StreamReadReply = connection.xread_options(&[&stream], &[">"], &options).await.unwrap();
+
+        let msg = router.next_message().await.expect("Should claim the pending entry");
+        assert_eq!(msg.get_content_str(), data);
+        router.task_done(msg).await;
+        let pending: StreamPendingReply = connection.xpending(&stream, "router").await.unwrap();
+        assert_eq!(pending.count(), 0);
+        let _: i64 = connection.del(&stream).await.unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use crate::interfaces::{Publisher, QMessage, RawMessage};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+
+#[derive(Serialize, Deserialize)]
+pub struct Schedule {
+    /// Cron expression evaluated in UTC, with a leading seconds field,
+    /// eg. `0 */15 * * * *`. Set either this or `interval_seconds`
+    pub cron: Option<String>,
+    pub interval_seconds: Option<u64>,
+    /// Sent on every fire. String values in its payload can use `{{fire_time}}`
+    /// and `{{fire_timestamp}}`
+    pub message: QMessage,
+    #[serde(skip_serializing, skip_deserializing)]
+    schedule: Option<cron::Schedule>,
+    #[serde(skip_serializing, skip_deserializing)]
+    next_fire: Option<DateTime<Utc>>
+}
+// the schedule is parsed on init so a clone only carries the config
+impl Clone for Schedule {
+    fn clone(&self) -> Self {
+        Self {
+            cron: self.cron.clone(),
+            interval_seconds: self.interval_seconds,
+            message: self.message.clone(),
+            schedule: None,
+            next_fire: None
+        }
+    }
+}
+impl fmt::Debug for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Schedule")
+            .field("cron", &self.cron)
+            .field("interval_seconds", &self.interval_seconds)
+            .field("message", &self.message)
+            .field("next_fire", &self.next_fire)
+            .finish()
+    }
+}
+
+pub struct ScheduleMsg {
+    fire_time: DateTime<Utc>,
+    msg: String
+}
+impl RawMessage for ScheduleMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::from([("fire_time".to_string(), self.fire_time.to_rfc3339())])
+    }
+}
+
+/// Replaces the placeholders in every string value of the payload
+fn render(value: &mut serde_json::Value, fire_time: &DateTime<Utc>) {
+    match value {
+        serde_json::Value::String(s) => {
+            *s = s.replace("{{fire_time}}", &fire_time.to_rfc3339())
+                .replace("{{fire_timestamp}}", &fire_time.timestamp().to_string())
+        },
+        serde_json::Value::Array(values) => values.iter_mut().for_each(|v| render(v, fire_time)),
+        serde_json::Value::Object(map) => map.values_mut().for_each(|v| render(v, fire_time)),
+        _ => {}
+    }
+}
+
+impl Schedule {
+    /// Fires that were missed while a previous trigger was still running are skipped
+    /// rather than all sent at once
+    fn next_fire_after(&self, previous: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if let Some(schedule) = &self.schedule {
+            // the clock can read earlier than a fire time that was just sent, eg. when the
+            // sleep woke up early, which must not fire it again
+            let from = previous.map_or(now, |previous| previous.max(now));
+            return schedule.after(&from).next()
+        }
+        let interval = chrono::Duration::seconds(self.interval_seconds? as i64);
+        Some(match previous {
+            Some(previous) if previous + interval > now => previous + interval,
+            Some(_) => now,
+            None => now + interval
+        })
+    }
+    fn message_at(&self, fire_time: DateTime<Utc>) -> ScheduleMsg {
+        let mut message = serde_json::to_value(&self.message).expect("QMessage should always serialize");
+        render(&mut message["payload"], &fire_time);
+        ScheduleMsg {fire_time, msg: message.to_string()}
+    }
+}
+
+#[async_trait]
+impl Publisher for Schedule {
+    type PubMessage = ScheduleMsg;
+
+    fn repr(&self) -> String {
+        let (flow_name, deployment_name) = self.message.get_flow_deployment();
+        match (&self.cron, self.interval_seconds) {
+            (Some(cron), _) => format!("Schedule '{}' {}/{}", cron, flow_name, deployment_name),
+            (None, Some(seconds)) => format!("Schedule every {}s {}/{}", seconds, flow_name, deployment_name),
+            (None, None) => format!("Schedule {}/{}", flow_name, deployment_name)
+        }
+    }
+    async fn init(&mut self) {
+        match (&self.cron, self.interval_seconds) {
+            (Some(cron), None) => {
+                self.schedule = Some(cron::Schedule::from_str(cron).expect("Invalid cron expression"))
+            },
+            (None, Some(seconds)) if seconds > 0 => {},
+            _ => panic!("Schedule needs exactly one of cron or a non-zero interval_seconds")
+        }
+        self.next_fire = self.next_fire_after(None, Utc::now());
+    }
+    async fn next_message(&mut self) -> Option<ScheduleMsg> {
+        let fire_time = match self.next_fire {
+            Some(fire_time) => fire_time,
+            None => {
+                // a cron expression that never fires again, eg. one for a past year
+                std::future::pending::<()>().await;
+                return None
+            }
+        };
+        if let Ok(wait) = (fire_time - Utc::now()).to_std() {
+            tokio::time::sleep(wait).await;
+        }
+        self.next_fire = self.next_fire_after(Some(fire_time), Utc::now());
+        Some(self.message_at(fire_time))
+    }
+    async fn task_done(&mut self, _message: Self::PubMessage) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Publisher, RawMessage};
+
+    use super::Schedule;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_renders_fire_time_on_cron() {
+        let mut schedule: Schedule = serde_json::from_value(json!(
+            {
+                "cron": "0 */15 * * * *",
+                "message": {
+                    "flow_name": "Test Flow",
+                    "deployment_name": "test",
+                    "payload": {"window_end": "{{fire_time}}", "tags": ["at-{{fire_timestamp}}"], "count": 1}
+                }
+            }
+        )).unwrap();
+        schedule.init().await;
+
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 10, 7, 30).unwrap();
+        let fire_time = schedule.next_fire_after(None, now).unwrap();
+        assert_eq!(fire_time, Utc.with_ymd_and_hms(2024, 3, 1, 10, 15, 0).unwrap());
+
+        let msg = schedule.message_at(fire_time);
+        let content: serde_json::Value = serde_json::from_str(&msg.get_content_str()).unwrap();
+        assert_eq!(content["flow_name"], "Test Flow");
+        assert_eq!(content["payload"], json!(
+            {"window_end": "2024-03-01T10:15:00+00:00", "tags": ["at-1709288100"], "count": 1}
+        ));
+        assert_eq!(msg.get_attributes()["fire_time"], "2024-03-01T10:15:00+00:00");
+
+        let early = Utc.with_ymd_and_hms(2024, 3, 1, 10, 14, 59).unwrap();
+        assert_eq!(
+            schedule.next_fire_after(Some(fire_time), early).unwrap(),
+            Utc.with_ymd_and_hms(2024, 3, 1, 10, 30, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skips_missed_intervals() {
+        let schedule: Schedule = serde_json::from_value(json!(
+            {"interval_seconds": 60, "message": {"flow_name": "Test Flow", "deployment_name": "test"}}
+        )).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+        let first = schedule.next_fire_after(None, start).unwrap();
+        assert_eq!(first, Utc.with_ymd_and_hms(2024, 3, 1, 10, 1, 0).unwrap());
+        let on_time = schedule.next_fire_after(Some(first), first).unwrap();
+        assert_eq!(on_time, Utc.with_ymd_and_hms(2024, 3, 1, 10, 2, 0).unwrap());
+        let late = Utc.with_ymd_and_hms(2024, 3, 1, 10, 5, 30).unwrap();
+        assert_eq!(schedule.next_fire_after(Some(first), late).unwrap(), late);
+    }
+}
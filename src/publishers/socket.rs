@@ -0,0 +1,298 @@
+use crate::interfaces::{Error, FlowRun, Publisher, RawMessage};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+// lines waiting for their flow run to be triggered
+const CHANNEL_CAPACITY: usize = 100;
+const DEFAULT_MAX_LINE_BYTES: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct Socket {
+    /// Path of a Unix domain socket to listen on. Set either this or `tcp_address`
+    pub unix_path: Option<String>,
+    /// eg. `127.0.0.1:7000`
+    pub tcp_address: Option<String>,
+    /// Clients sending a longer line are disconnected. Defaults to 1 MiB
+    pub max_line_bytes: Option<usize>,
+    #[serde(skip_serializing, skip_deserializing)]
+    lines: Option<mpsc::Receiver<SocketMsg>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    server: Option<JoinHandle<()>>
+}
+// the listener is bound by the publisher that was initialised so a clone only carries the config
+impl Clone for Socket {
+    fn clone(&self) -> Self {
+        Self {
+            unix_path: self.unix_path.clone(),
+            tcp_address: self.tcp_address.clone(),
+            max_line_bytes: self.max_line_bytes,
+            lines: None,
+            server: None
+        }
+    }
+}
+impl fmt::Debug for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socket")
+            .field("unix_path", &self.unix_path)
+            .field("tcp_address", &self.tcp_address)
+            .field("max_line_bytes", &self.max_line_bytes)
+            .field("connected", &self.server.is_some())
+            .finish()
+    }
+}
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.abort()
+        }
+    }
+}
+
+/// A line held by its client connection until the router has tried to trigger its flow run
+pub struct SocketMsg {
+    msg: String,
+    client: String,
+    responder: oneshot::Sender<serde_json::Value>
+}
+impl SocketMsg {
+    fn reply(self, reply: serde_json::Value) {
+        // the client may have disconnected in the meantime, in which case there is no one to tell
+        let _ = self.responder.send(reply);
+    }
+}
+impl RawMessage for SocketMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::from([("client".to_string(), self.client.clone())])
+    }
+}
+
+/// Reads newline-delimited messages from one client and writes back a reply line for
+/// each, in order, until the client disconnects or sends a line longer than `max_line_bytes`
+async fn serve_client<S>(stream: S, client: String, max_line_bytes: usize, sender: mpsc::Sender<SocketMsg>)
+where
+    S: AsyncRead + AsyncWrite + Unpin
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut bytes = Vec::new();
+        // one byte over the limit is enough to tell the line is too long
+        match (&mut reader).take(max_line_bytes as u64 + 1).read_until(b'\n', &mut bytes).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        if bytes.len() > max_line_bytes && !bytes.ends_with(b"\n") {
+            let reply = json!({"status": "nack", "error": format!("Line is longer than {} bytes", max_line_bytes)});
+            let _ = writer.write_all(format!("{}\n", reply).as_bytes()).await;
+            return
+        }
+        let line = String::from_utf8_lossy(&bytes);
+        let line = line.trim_end_matches('\n').trim_end_matches('\r').to_string();
+        if line.trim().is_empty() {
+            continue
+        }
+        let (responder, reply) = oneshot::channel();
+        let msg = SocketMsg {msg: line, client: client.clone(), responder};
+        let reply = match sender.send(msg).await {
+            Ok(_) => reply.await.ok(),
+            Err(_) => None
+        }.unwrap_or_else(|| json!({"status": "nack", "error": "Router is not running"}));
+        if writer.write_all(format!("{}\n", reply).as_bytes()).await.is_err() {
+            return
+        }
+    }
+}
+
+async fn accept_tcp(listener: TcpListener, max_line_bytes: usize, sender: mpsc::Sender<SocketMsg>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tokio::spawn(serve_client(stream, addr.to_string(), max_line_bytes, sender.clone()));
+            },
+            Err(e) => println!("Socket: Failed to accept TCP client: {:?}", e)
+        }
+    }
+}
+
+/// Removes a socket file left behind by a previous run, which would make the bind fail.
+/// Anything else at the path, including a socket another process is listening on, is left alone
+#[cfg(unix)]
+fn remove_stale_socket(path: &str) -> Result<(), String> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => Err(format!("{} is in use by another process", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                std::fs::remove_file(path).map_err(|e| e.to_string())
+            },
+            Err(e) => Err(format!("Unable to check whether {} is in use: {}", path, e))
+        },
+        Ok(_) => Err(format!("{} exists and is not a socket", path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+#[cfg(unix)]
+async fn accept_unix(listener: UnixListener, path: String, max_line_bytes: usize, sender: mpsc::Sender<SocketMsg>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_client(stream, path.clone(), max_line_bytes, sender.clone()));
+            },
+            Err(e) => println!("Socket: Failed to accept Unix client: {:?}", e)
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for Socket {
+    type PubMessage = SocketMsg;
+
+    fn repr(&self) -> String {
+        match (&self.unix_path, &self.tcp_address) {
+            (Some(path), _) => format!("Socket unix:{}", path),
+            (None, Some(address)) => format!("Socket tcp:{}", address),
+            (None, None) => "Socket".to_string()
+        }
+    }
+    async fn init(&mut self) {
+        let (sender, lines) = mpsc::channel(CHANNEL_CAPACITY);
+        let max_line_bytes = self.max_line_bytes.unwrap_or(DEFAULT_MAX_LINE_BYTES);
+        let server = match (&self.unix_path, &self.tcp_address) {
+            #[cfg(unix)]
+            (Some(path), None) => {
+                if let Err(e) = remove_stale_socket(path) {
+                    panic!("Unable to bind the Unix socket: {}", e)
+                }
+                let listener = UnixListener::bind(path).expect("Unable to bind the Unix socket");
+                tokio::spawn(accept_unix(listener, path.clone(), max_line_bytes, sender))
+            },
+            #[cfg(not(unix))]
+            (Some(_), None) => panic!("unix_path is only supported on Unix, use tcp_address instead"),
+            (None, Some(address)) => {
+                let listener = TcpListener::bind(address).await.expect("Unable to bind the TCP address");
+                tokio::spawn(accept_tcp(listener, max_line_bytes, sender))
+            },
+            _ => panic!("Socket needs exactly one of unix_path or tcp_address")
+        };
+        self.server = Some(server);
+        self.lines = Some(lines);
+    }
+    async fn next_message(&mut self) -> Option<SocketMsg> {
+        self.lines.as_mut().expect(
+            "Cannot await lines without the socket being initialised"
+        ).recv().await
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        message.reply(json!({"status": "ack"}))
+    }
+    async fn task_triggered(&mut self, message: Self::PubMessage, flow_run: &FlowRun) {
+        message.reply(json!({"status": "ack", "flow_run_id": &flow_run.id, "flow_run_name": &flow_run.name}))
+    }
+    async fn task_failed(&mut self, message: Self::PubMessage, error: &Error) {
+        message.reply(json!({"status": "nack", "error": error.to_string()}))
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, error: &Error) {
+        message.reply(json!({"status": "nack", "error": error.to_string()}))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::interfaces::{Error, FlowRun, Publisher, RawMessage};
+
+    use super::{remove_stale_socket, Socket};
+    use serde_json::json;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixStream;
+
+    #[tokio::test]
+    async fn test_replies_per_line_over_unix_socket() {
+        let suffix: u32 = rand::random();
+        let path = std::env::temp_dir().join(format!("router-{}.sock", suffix));
+        let path = path.to_str().unwrap().to_string();
+        let mut socket: Socket = serde_json::from_value(json!({"unix_path": &path})).unwrap();
+        socket.init().await;
+
+        let client = tokio::spawn({
+            let path = path.clone();
+            async move {
+                let stream = UnixStream::connect(path).await.unwrap();
+                let (reader, mut writer) = stream.into_split();
+                writer.write_all(b"{\"flow_name\": \"Test Flow\", \"deployment_name\": \"test\"}\nnot json\n").await.unwrap();
+                let mut lines = BufReader::new(reader).lines();
+                let ack = lines.next_line().await.unwrap().unwrap();
+                let nack = lines.next_line().await.unwrap().unwrap();
+                (ack, nack)
+            }
+        });
+
+        let msg = socket.next_message().await.unwrap();
+        assert_eq!(msg.get_content_str(), "{\"flow_name\": \"Test Flow\", \"deployment_name\": \"test\"}");
+        let flow_run = FlowRun {id: "1234".to_string(), name: "brave-fox".to_string()};
+        socket.task_triggered(msg, &flow_run).await;
+        let msg = socket.next_message().await.unwrap();
+        socket.task_invalid(msg, &Error::InvalidMessage("test".to_string())).await;
+
+        let (ack, nack) = client.await.unwrap();
+        let ack: serde_json::Value = serde_json::from_str(&ack).unwrap();
+        assert_eq!(ack, json!({"status": "ack", "flow_run_id": "1234", "flow_run_name": "brave-fox"}));
+        let nack: serde_json::Value = serde_json::from_str(&nack).unwrap();
+        assert_eq!(nack["status"], "nack");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disconnects_clients_sending_long_lines() {
+        let suffix: u32 = rand::random();
+        let path = std::env::temp_dir().join(format!("router-{}.sock", suffix));
+        let path = path.to_str().unwrap().to_string();
+        let mut socket: Socket = serde_json::from_value(json!({"unix_path": &path, "max_line_bytes": 16})).unwrap();
+        socket.init().await;
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all("x".repeat(64).as_bytes()).await.unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        let nack: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(nack["status"], "nack");
+        assert!(lines.next_line().await.unwrap().is_none(), "Client should be disconnected");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_only_removes_stale_sockets() {
+        let suffix: u32 = rand::random();
+        let path = std::env::temp_dir().join(format!("router-{}.sock", suffix));
+        let path = path.to_str().unwrap().to_string();
+        assert!(remove_stale_socket(&path).is_ok());
+
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(std::path::Path::new(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(remove_stale_socket(&path).is_err(), "Should not remove a socket that is still listening");
+        assert!(std::path::Path::new(&path).exists());
+
+        drop(listener);
+        remove_stale_socket(&path).unwrap();
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rusqlite::{params, Connection};
+
+const DEFAULT_TABLE: &str = "events";
+const DEFAULT_BATCH_SIZE: usize = 10;
+const DEFAULT_LEASE_SECONDS: i64 = 300;
+const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+// how long to wait for a producer to finish writing before giving up on a statement
+const BUSY_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Serialize, Deserialize)]
+pub struct Sqlite {
+    /// The database file, created if it does not exist
+    pub path: String,
+    /// Defaults to `events`
+    pub table: Option<String>,
+    /// Set `processed_at` on processed rows rather than deleting them
+    #[serde(default)]
+    pub keep_processed: bool,
+    pub batch_size: Option<usize>,
+    /// Claimed rows that are not processed within this time are claimed again
+    pub lease_seconds: Option<i64>,
+    pub poll_interval_ms: Option<u64>,
+    #[serde(skip_serializing, skip_deserializing)]
+    connection: Option<Arc<Mutex<Connection>>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    messages: Vec<SqliteMsg>
+}
+// the connection is opened by the publisher that was initialised so a clone only carries the config
+impl Clone for Sqlite {
+    fn clone(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            table: self.table.clone(),
+            keep_processed: self.keep_processed,
+            batch_size: self.batch_size,
+            lease_seconds: self.lease_seconds,
+            poll_interval_ms: self.poll_interval_ms,
+            connection: None,
+            messages: Vec::new()
+        }
+    }
+}
+impl fmt::Debug for Sqlite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sqlite")
+            .field("path", &self.path)
+            .field("table", &self.table)
+            .field("keep_processed", &self.keep_processed)
+            .field("batch_size", &self.batch_size)
+            .field("lease_seconds", &self.lease_seconds)
+            .field("poll_interval_ms", &self.poll_interval_ms)
+            .field("connected", &self.connection.is_some())
+            .finish()
+    }
+}
+
+pub struct SqliteMsg {
+    id: i64,
+    msg: String
+}
+impl RawMessage for SqliteMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::from([("id".to_string(), self.id.to_string())])
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Producers only need to insert a `payload`, the other columns are managed by the router.
+/// Timestamps are Unix seconds
+fn schema(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {table} (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            payload TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)),
+            leased_until INTEGER,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            processed_at INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS {index} ON {table} (processed_at, leased_until);",
+        table = quote_ident(table),
+        index = quote_ident(&format!("{}_pending", table))
+    )
+}
+
+impl Sqlite {
+    fn table(&self) -> String {
+        quote_ident(self.table.as_deref().unwrap_or(DEFAULT_TABLE))
+    }
+    /// Runs `query` on the blocking thread pool, as SQLite blocks the thread while it
+    /// waits on the disk or for a producer's write lock
+    async fn with_connection<T, F>(&self, query: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static
+    {
+        let connection = self.connection.clone().expect(
+            "Cannot query without the SQLite connection being initialised"
+        );
+        tokio::task::spawn_blocking(move || {
+            query(&connection.lock().expect("SQLite connection lock is poisoned"))
+        }).await.expect("SQLite query panicked")
+    }
+    /// Leases the oldest pending rows whose lease is not held or has expired
+    async fn claim(&mut self) -> rusqlite::Result<()> {
+        let now = unix_now();
+        let query = format!(
+            "UPDATE {table} SET leased_until = ?1, attempts = attempts + 1 WHERE id IN (
+                SELECT id FROM {table} WHERE processed_at IS NULL AND (leased_until IS NULL OR leased_until <= ?2)
+                ORDER BY id LIMIT ?3
+            ) RETURNING id, payload",
+            table = self.table()
+        );
+        let lease = self.lease_seconds.unwrap_or(DEFAULT_LEASE_SECONDS);
+        let batch_size = self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE) as i64;
+        let mut claimed = self.with_connection(move |connection| {
+            let mut statement = connection.prepare(&query)?;
+            let rows = statement.query_map(params![now + lease, now, batch_size], |row| {
+                Ok(SqliteMsg {id: row.get(0)?, msg: row.get(1)?})
+            })?;
+            rows.collect::<rusqlite::Result<Vec<SqliteMsg>>>()
+        }).await?;
+        // messages are popped from the back so sort descending to keep the insert order
+        claimed.sort_by_key(|msg| std::cmp::Reverse(msg.id));
+        self.messages.extend(claimed);
+        Ok(())
+    }
+    async fn mark_processed(&self, message: &SqliteMsg) {
+        let id = message.id;
+        let result = if self.keep_processed {
+            let query = format!("UPDATE {} SET processed_at = ?2, leased_until = NULL WHERE id = ?1", self.table());
+            self.with_connection(move |connection| connection.execute(&query, params![id, unix_now()])).await
+        } else {
+            let query = format!("DELETE FROM {} WHERE id = ?1", self.table());
+            self.with_connection(move |connection| connection.execute(&query, params![id])).await
+        };
+        if let Err(e) = result {
+            println!("{}: Failed to mark row {} as processed: {:?}", self.repr(), message.id, e)
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for Sqlite {
+    type PubMessage = SqliteMsg;
+
+    fn repr(&self) -> String {
+        format!("SQLite {}/{}", self.path, self.table.as_deref().unwrap_or(DEFAULT_TABLE))
+    }
+    async fn init(&mut self) {
+        let connection = Connection::open(&self.path).expect("Unable to open the SQLite database");
+        connection.busy_timeout(Duration::from_millis(BUSY_TIMEOUT_MS)).expect("Unable to set the busy timeout");
+        // lets producers insert while the router is reading
+        connection.pragma_update(None, "journal_mode", "WAL").expect("Unable to enable WAL mode");
+        connection.execute_batch(
+            &schema(self.table.as_deref().unwrap_or(DEFAULT_TABLE))
+        ).expect("Unable to create the queue table");
+        self.connection = Some(Arc::new(Mutex::new(connection)));
+    }
+    async fn next_message(&mut self) -> Option<SqliteMsg> {
+        if self.messages.is_empty() {
+            if let Err(e) = self.claim().await {
+                println!("{}: Failed to claim rows: {:?}", self.repr(), e)
+            }
+            if self.messages.is_empty() {
+                tokio::time::sleep(Duration::from_millis(
+                    self.poll_interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS)
+                )).await;
+            }
+        }
+        self.messages.pop()
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        self.mark_processed(&message).await
+    }
+    async fn task_failed(&mut self, _message: Self::PubMessage, _error: &Error) {
+        // the lease is kept so the row is retried once it expires
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        self.mark_processed(&message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Error, Publisher, RawMessage};
+
+    use super::Sqlite;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_claims_and_deletes_rows() {
+        let suffix: u32 = rand::random();
+        let path = std::env::temp_dir().join(format!("router-{}.db", suffix));
+        let config = json!({"path": path.to_str().unwrap(), "poll_interval_ms": 10});
+        let mut router: Sqlite = serde_json::from_value(config.clone()).unwrap();
+        router.init().await;
+        let mut other: Sqlite = serde_json::from_value(config).unwrap();
+        other.init().await;
+
+        let first = json!({"flow_name": "Test Flow", "deployment_name": "first"}).to_string();
+        let second = json!({"flow_name": "Test Flow", "deployment_name": "second"}).to_string();
+        for payload in [first.clone(), second.clone()] {
+            router.with_connection(move |connection| {
+                connection.execute("INSERT INTO events (payload) VALUES (?1)", [payload])
+            }).await.unwrap();
+        }
+
+        let msg = router.next_message().await.expect("Should claim the first row");
+        assert_eq!(msg.get_content_str(), first);
+        assert_eq!(msg.get_attributes()["id"], "1");
+        router.task_done(msg).await;
+        let msg = router.next_message().await.unwrap();
+        assert_eq!(msg.get_content_str(), second);
+        router.task_failed(msg, &Error::PrefectApiError("test".to_string())).await;
+        assert!(other.next_message().await.is_none(), "Leased row should not be claimed again");
+
+        let remaining: Vec<(i64, i64, i64)> = router.with_connection(|connection| {
+            connection.prepare("SELECT id, attempts, created_at FROM events")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect()
+        }).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!((remaining[0].0, remaining[0].1), (2, 1));
+        assert!((remaining[0].2 - super::unix_now()).abs() < 60, "created_at should be in Unix seconds");
+        drop(router);
+        drop(other);
+        for suffix in ["", "-wal", "-shm"] {
+            let file = format!("{}{}", path.display(), suffix);
+            if std::path::Path::new(&file).exists() {
+                std::fs::remove_file(file).unwrap();
+            }
+        }
+    }
+}
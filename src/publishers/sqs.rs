@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use std::time::{Duration, Instant};
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_sqs::types::{ChangeMessageVisibilityBatchRequestEntry, Message, MessageAttributeValue};
+use aws_sdk_sqs::Client;
+
+use crate::interfaces::{Error, Publisher, RawMessage};
+
+const DEFAULT_BATCH_SIZE: i32 = 10;
+const DEFAULT_WAIT_TIME_SECONDS: i32 = 20;
+// the SQS default for queues created without a visibility timeout
+const DEFAULT_VISIBILITY_TIMEOUT: i32 = 30;
+
+impl RawMessage for Message {
+    fn get_content_str(&self) -> String {
+        self.body().unwrap_or_default().to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sqs {
+    pub queue_url: String,
+    /// Overrides the AWS endpoint, eg. to point at LocalStack or ElasticMQ
+    pub endpoint_url: Option<String>,
+    /// Defaults to the region of the standard AWS provider chain
+    pub region: Option<String>,
+    /// Number of messages received per call, between 1 and 10
+    pub batch_size: Option<i32>,
+    /// Seconds a received message stays hidden from other consumers. Defaults to 30
+    pub visibility_timeout: Option<i32>,
+    /// Long-polling wait of each receive call, up to 20 seconds
+    pub wait_time_seconds: Option<i32>,
+    /// Queue that messages which cannot be parsed are sent to, with the error in a
+    /// `router-error` attribute. They are deleted either way
+    pub dead_letter_queue_url: Option<String>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    client: Option<Client>,
+    #[serde(skip_serializing, skip_deserializing)]
+    messages: Vec<Message>,
+    #[serde(skip_serializing, skip_deserializing)]
+    hidden_since: Option<Instant>
+}
+
+impl Sqs {
+    fn client(&self) -> &Client {
+        self.client.as_ref().expect(
+            "Cannot use the queue without the SQS client being initialised"
+        )
+    }
+    fn visibility_timeout(&self) -> i32 {
+        self.visibility_timeout.unwrap_or(DEFAULT_VISIBILITY_TIMEOUT)
+    }
+    async fn receive_messages(&mut self) {
+        let response = self.client().receive_message()
+            .queue_url(&self.queue_url)
+            .max_number_of_messages(self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE))
+            .wait_time_seconds(self.wait_time_seconds.unwrap_or(DEFAULT_WAIT_TIME_SECONDS))
+            .visibility_timeout(self.visibility_timeout())
+            .send()
+            .await;
+        match response {
+            // popped from the back so reverse to keep the received order
+            Ok(output) => {
+                self.messages = output.messages.unwrap_or_default().into_iter().rev().collect();
+                self.hidden_since = Some(Instant::now());
+            },
+            Err(e) => {
+                println!("{}: Failed to receive messages: {:?}", self.repr(), e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+    /// Messages of a batch wait for the triggers of the ones before them, so once half of
+    /// the visibility timeout has passed the remaining ones are hidden for another timeout
+    async fn extend_visibility(&mut self) {
+        let timeout = self.visibility_timeout();
+        let due = self.hidden_since.is_some_and(
+            |since| since.elapsed() >= Duration::from_secs(timeout.max(0) as u64 / 2)
+        );
+        if !due || self.messages.is_empty() {
+            return
+        }
+        let entries = self.messages.iter().enumerate().filter_map(|(i, message)| {
+            ChangeMessageVisibilityBatchRequestEntry::builder()
+                .id(i.to_string())
+                .set_receipt_handle(message.receipt_handle.clone())
+                .visibility_timeout(timeout)
+                .build()
+                .ok()
+        }).collect();
+        let changed = self.client().change_message_visibility_batch()
+            .queue_url(&self.queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await;
+        match changed {
+            Ok(output) => for failed in output.failed() {
+                println!("{}: Failed to extend visibility of a message: {:?}", self.repr(), failed)
+            },
+            Err(e) => println!("{}: Failed to extend visibility: {:?}", self.repr(), e)
+        }
+        self.hidden_since = Some(Instant::now());
+    }
+    async fn dead_letter(&self, message: &Message, queue_url: &str, error: &Error) -> Result<(), String> {
+        let attribute = MessageAttributeValue::builder()
+            .data_type("String")
+            .string_value(error.to_string())
+            .build()
+            .map_err(|e| e.to_string())?;
+        self.client().send_message()
+            .queue_url(queue_url)
+            .message_body(message.body().unwrap_or_default())
+            .message_attributes("router-error", attribute)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Publisher for Sqs {
+    type PubMessage = Message;
+
+    fn repr(&self) -> String {
+        format!("SQS {}", &self.queue_url)
+    }
+    async fn init(&mut self) {
+        // credentials come from the standard AWS provider chain
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = &self.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+        if let Some(endpoint_url) = &self.endpoint_url {
+            loader = loader.endpoint_url(endpoint_url);
+        }
+        self.client = Some(Client::new(&loader.load().await));
+    }
+
+    async fn next_message(&mut self) -> Option<Message> {
+        if self.messages.is_empty() {
+            self.receive_messages().await;
+        } else {
+            self.extend_visibility().await;
+        }
+        self.messages.pop()
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        let deleted = self.client().delete_message()
+            .queue_url(&self.queue_url)
+            .set_receipt_handle(message.receipt_handle)
+            .send()
+            .await;
+        if let Err(e) = deleted {
+            println!("{}: Failed to delete message: {:?}", self.repr(), e)
+        }
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, error: &Error) {
+        // without a redrive policy the message would otherwise come back forever
+        if let Some(queue_url) = &self.dead_letter_queue_url {
+            if let Err(e) = self.dead_letter(&message, queue_url, error).await {
+                println!("{}: Failed to dead-letter message: {}", self.repr(), e);
+                return
+            }
+        }
+        self.task_done(message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Error, Publisher, RawMessage};
+
+    use super::Sqs;
+    use aws_sdk_sqs::types::QueueAttributeName;
+    use serde_json::json;
+
+    // Requires LocalStack or ElasticMQ, eg. `make start-localstack`. Any credentials are
+    // accepted so set AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY to dummy values
+    #[tokio::test]
+    #[ignore]
+    async fn test_e2e_local_endpoint() {
+        let endpoint_url = std::env::var("SQS_ENDPOINT_URL").unwrap_or("http://127.0.0.1:4566".to_string());
+        let suffix: u32 = rand::random();
+        let mut sqs: Sqs = serde_json::from_value(json!(
+            {
+                "queue_url": "",
+                "endpoint_url": endpoint_url,
+                "region": "us-east-1",
+                "wait_time_seconds": 1,
+                "visibility_timeout": 30
+            }
+        )).unwrap();
+        sqs.init().await;
+        let client = sqs.client.clone().unwrap();
+        sqs.queue_url = client.create_queue()
+            .queue_name(format!("router-test-{}", suffix))
+            .send().await.unwrap()
+            .queue_url.unwrap();
+        let data = json!({"flow_name": "Test Flow", "deployment_name": "test"}).to_string();
+        client.send_message().queue_url(&sqs.queue_url).message_body(&data).send().await.unwrap();
+
+        let msg = sqs.next_message().await.expect("Should receive the test message");
+        assert_eq!(msg.get_content_str(), data);
+        sqs.task_done(msg).await;
+
+        let attributes = client.get_queue_attributes()
+            .queue_url(&sqs.queue_url)
+            .attribute_names(QueueAttributeName::All)
+            .send().await.unwrap();
+        let counts = attributes.attributes.unwrap();
+        assert_eq!(counts[&QueueAttributeName::ApproximateNumberOfMessages], "0");
+        assert_eq!(counts[&QueueAttributeName::ApproximateNumberOfMessagesNotVisible], "0");
+        client.delete_queue().queue_url(&sqs.queue_url).send().await.unwrap();
+    }
+
+    // Requires LocalStack or ElasticMQ, eg. `make start-localstack`
+    #[tokio::test]
+    #[ignore]
+    async fn test_invalid_message_is_dead_lettered() {
+        let endpoint_url = std::env::var("SQS_ENDPOINT_URL").unwrap_or("http://127.0.0.1:4566".to_string());
+        let suffix: u32 = rand::random();
+        let mut sqs: Sqs = serde_json::from_value(json!(
+            {"queue_url": "", "endpoint_url": endpoint_url, "region": "us-east-1", "wait_time_seconds": 1}
+        )).unwrap();
+        sqs.init().await;
+        let client = sqs.client.clone().unwrap();
+        let mut queue_urls = Vec::new();
+        for name in ["router-test", "router-test-dlq"] {
+            queue_urls.push(client.create_queue()
+                .queue_name(format!("{}-{}", name, suffix))
+                .send().await.unwrap()
+                .queue_url.unwrap());
+        }
+        sqs.queue_url = queue_urls[0].clone();
+        sqs.dead_letter_queue_url = Some(queue_urls[1].clone());
+        client.send_message().queue_url(&sqs.queue_url).message_body("not json").send().await.unwrap();
+
+        let msg = sqs.next_message().await.expect("Should receive the test message");
+        sqs.task_invalid(msg, &Error::InvalidMessage("not json".to_string())).await;
+
+        let received = client.receive_message()
+            .queue_url(&queue_urls[1])
+            .message_attribute_names("All")
+            .wait_time_seconds(1)
+            .send().await.unwrap()
+            .messages.unwrap_or_default();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].body(), Some("not json"));
+        assert!(received[0].message_attributes().unwrap().contains_key("router-error"));
+        for url in &queue_urls {
+            client.delete_queue().queue_url(url).send().await.unwrap();
+        }
+    }
+}
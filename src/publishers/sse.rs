@@ -0,0 +1,371 @@
+use crate::interfaces::{Error, Publisher, RawMessage};
+use crate::routing::Route;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+const DEFAULT_RETRY_MS: u64 = 1000;
+
+#[derive(Serialize, Deserialize)]
+pub struct Sse {
+    /// The `text/event-stream` endpoint
+    pub url: String,
+    /// Extra request headers, eg. `Authorization`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// File the id of the last processed event is kept in so a restart resumes after it
+    pub last_event_id_path: Option<String>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(skip_serializing, skip_deserializing)]
+    client: reqwest::Client,
+    #[serde(skip_serializing, skip_deserializing)]
+    response: Option<reqwest::Response>,
+    #[serde(skip_serializing, skip_deserializing)]
+    parser: EventParser,
+    #[serde(skip_serializing, skip_deserializing)]
+    messages: Vec<SseMsg>,
+    /// Id of the last event that was processed, sent as `Last-Event-ID` on reconnect
+    #[serde(skip_serializing, skip_deserializing)]
+    last_event_id: Option<String>
+}
+// the response cannot be shared between threads so a clone only carries the config
+impl Clone for Sse {
+    fn clone(&self) -> Self {
+        Self {
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+            last_event_id_path: self.last_event_id_path.clone(),
+            routes: self.routes.clone(),
+            client: reqwest::Client::new(),
+            response: None,
+            parser: EventParser::default(),
+            messages: Vec::new(),
+            last_event_id: None
+        }
+    }
+}
+impl fmt::Debug for Sse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // headers are left out as they usually hold credentials
+        f.debug_struct("Sse")
+            .field("url", &self.url)
+            .field("last_event_id_path", &self.last_event_id_path)
+            .field("routes", &self.routes)
+            .field("last_event_id", &self.last_event_id)
+            .field("connected", &self.response.is_some())
+            .finish()
+    }
+}
+
+pub struct SseMsg {
+    event: String,
+    id: Option<String>,
+    msg: String
+}
+impl RawMessage for SseMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        let mut attributes = HashMap::from([("event".to_string(), self.event.clone())]);
+        if let Some(id) = &self.id {
+            attributes.insert("id".to_string(), id.clone());
+        }
+        attributes
+    }
+}
+
+/// Turns the chunks of an event stream into events, following the parsing rules of
+/// the HTML spec. Lines can be split across chunks
+#[derive(Default)]
+struct EventParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+    /// Carried over to events that do not set their own id
+    id: Option<String>,
+    /// Reconnection time requested by the server
+    retry_ms: Option<u64>
+}
+impl EventParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<SseMsg> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches('\n').trim_end_matches('\r');
+            if let Some(event) = self.process_line(line) {
+                events.push(event)
+            }
+        }
+        events
+    }
+    fn process_line(&mut self, line: &str) -> Option<SseMsg> {
+        if line.is_empty() {
+            return self.dispatch()
+        }
+        if line.starts_with(':') {
+            return None
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => self.data.push(value.to_string()),
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            "retry" => if let Ok(retry_ms) = value.parse() {
+                self.retry_ms = Some(retry_ms)
+            },
+            _ => {}
+        }
+        None
+    }
+    fn dispatch(&mut self) -> Option<SseMsg> {
+        let event = self.event.take().unwrap_or("message".to_string());
+        if self.data.is_empty() {
+            return None
+        }
+        let msg = self.data.join("\n");
+        self.data.clear();
+        Some(SseMsg {event, id: self.id.clone(), msg})
+    }
+    /// Drops a partially received event, eg. when the connection was lost
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.event = None;
+        self.data.clear();
+    }
+}
+
+impl Sse {
+    async fn connect(&mut self) -> Result<(), String> {
+        let mut request = self.client.get(&self.url).header("Accept", "text/event-stream");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        if let Some(id) = &self.last_event_id {
+            request = request.header("Last-Event-ID", id);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Server responded with {}", response.status()))
+        }
+        self.parser.reset();
+        // the id of the last processed event rather than the last received one
+        self.parser.id = self.last_event_id.clone();
+        self.response = Some(response);
+        Ok(())
+    }
+    async fn save_last_event_id(&self, id: &str) -> Result<(), String> {
+        let path = match &self.last_event_id_path {
+            Some(path) => path,
+            None => return Ok(())
+        };
+        // written aside and renamed so a crash never leaves a truncated file
+        let tmp_path = format!("{}.tmp", path);
+        tokio::fs::write(&tmp_path, id).await.map_err(|e| e.to_string())?;
+        tokio::fs::rename(&tmp_path, path).await.map_err(|e| e.to_string())
+    }
+    async fn processed(&mut self, message: SseMsg) {
+        let id = match message.id {
+            Some(id) => id,
+            None => return
+        };
+        if let Err(e) = self.save_last_event_id(&id).await {
+            println!("{}: Failed to save the last event id {}: {}", self.repr(), id, e)
+        }
+        self.last_event_id = Some(id);
+    }
+}
+
+#[async_trait]
+impl Publisher for Sse {
+    type PubMessage = SseMsg;
+
+    fn repr(&self) -> String {
+        format!("SSE {}", self.url)
+    }
+    async fn init(&mut self) {
+        if let Some(path) = &self.last_event_id_path {
+            match tokio::fs::read_to_string(path).await {
+                // the file may have been written by hand, with a trailing newline
+                Ok(id) => self.last_event_id = Some(id.trim().to_string()).filter(|id| !id.is_empty()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => panic!("Unable to read the last event id from {}: {:?}", path, e)
+            }
+        }
+        // a server that is down at startup is retried by next_message, like a dropped connection
+        if let Err(e) = self.connect().await {
+            println!("{}: Failed to connect: {}", self.repr(), e)
+        }
+    }
+    async fn next_message(&mut self) -> Option<SseMsg> {
+        if self.messages.is_empty() {
+            let response = match self.response.as_mut() {
+                Some(response) => response,
+                None => {
+                    tokio::time::sleep(Duration::from_millis(self.parser.retry_ms.unwrap_or(DEFAULT_RETRY_MS))).await;
+                    if let Err(e) = self.connect().await {
+                        println!("{}: Failed to reconnect: {}", self.repr(), e);
+                    }
+                    return None
+                }
+            };
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    // messages are popped from the back so reverse to keep the stream order
+                    self.messages.extend(self.parser.feed(&chunk).into_iter().rev())
+                },
+                Ok(None) => {
+                    println!("{}: Stream ended, reconnecting", self.repr());
+                    self.response = None
+                },
+                Err(e) => {
+                    println!("{}: Connection lost: {}", self.repr(), e);
+                    self.response = None
+                }
+            }
+        }
+        self.messages.pop()
+    }
+    async fn task_done(&mut self, message: Self::PubMessage) {
+        self.processed(message).await
+    }
+    async fn task_failed(&mut self, _message: Self::PubMessage, _error: &Error) {
+        // reconnecting after the retry time resumes after the last processed event, so the
+        // failed event and any received after it are sent again instead of lost
+        println!("{}: Failed to trigger an event, reconnecting", self.repr());
+        self.messages.clear();
+        self.response = None
+    }
+    async fn task_invalid(&mut self, message: Self::PubMessage, _error: &Error) {
+        // skipped on reconnect as well, it would not parse any better the second time
+        self.processed(message).await
+    }
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Error, Publisher, RawMessage};
+
+    use super::{EventParser, Sse};
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parses_events_split_across_chunks() {
+        let mut parser = EventParser::default();
+        assert!(parser.feed(b": keep-alive\n\nevent: deploy\nid: 7\nda").is_empty());
+        let events = parser.feed(b"ta: line one\r\ndata:line two\n\ndata: no id\n\nretry: 5000\n");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].get_content_str(), "line one\nline two");
+        assert_eq!(events[0].get_attributes()["event"], "deploy");
+        assert_eq!(events[0].get_attributes()["id"], "7");
+        assert_eq!(events[1].get_attributes()["event"], "message");
+        assert_eq!(events[1].get_attributes()["id"], "7", "Id should carry over to the next event");
+        assert_eq!(parser.retry_ms, Some(5000));
+    }
+
+    #[tokio::test]
+    async fn test_resumes_from_last_processed_event() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut last_event_ids = Vec::new();
+            let bodies = [
+                "retry: 10\nid: 1\ndata: first\n\nid: 2\ndata: second\n\nid: 3\ndata: third\n\n",
+                "id: 2\ndata: second\n\nid: 3\ndata: third\n\n"
+            ];
+            for body in bodies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut last_event_id = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break
+                    }
+                    if let Some(id) = line.to_lowercase().strip_prefix("last-event-id:") {
+                        last_event_id = Some(id.trim().to_string())
+                    }
+                }
+                last_event_ids.push(last_event_id);
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n{}", body).unwrap();
+            }
+            last_event_ids
+        });
+
+        let suffix: u32 = rand::random();
+        let path = std::env::temp_dir().join(format!("router-sse-{}", suffix));
+        let mut sse: Sse = serde_json::from_value(json!(
+            {"url": url, "last_event_id_path": path.to_str().unwrap()}
+        )).unwrap();
+        sse.init().await;
+
+        let mut received = Vec::new();
+        while received.len() < 4 {
+            if let Some(msg) = sse.next_message().await {
+                received.push(msg.get_content_str());
+                // only the first attempt at the second event fails
+                if received == ["first", "second"] {
+                    sse.task_failed(msg, &Error::PrefectApiError("test".to_string())).await
+                } else {
+                    sse.task_done(msg).await
+                }
+            }
+        }
+        assert_eq!(received, vec!["first", "second", "second", "third"]);
+        assert_eq!(server.join().unwrap(), vec![None, Some("1".to_string())]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "3");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connects_once_the_server_is_up() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let suffix: u32 = rand::random();
+        let path = std::env::temp_dir().join(format!("router-sse-{}", suffix));
+        std::fs::write(&path, "5\n").unwrap();
+        let mut sse: Sse = serde_json::from_value(json!(
+            {"url": format!("http://{}/events", addr), "last_event_id_path": path.to_str().unwrap()}
+        )).unwrap();
+        sse.init().await;
+        assert!(sse.response.is_none());
+
+        let listener = TcpListener::bind(addr).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut last_event_id = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break
+                }
+                if let Some(id) = line.to_lowercase().strip_prefix("last-event-id:") {
+                    last_event_id = Some(id.trim().to_string())
+                }
+            }
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\nid: 6\ndata: sixth\n\n").unwrap();
+            last_event_id
+        });
+        let msg = loop {
+            if let Some(msg) = sse.next_message().await {
+                break msg
+            }
+        };
+        assert_eq!(msg.get_content_str(), "sixth");
+        assert_eq!(server.join().unwrap(), Some("5".to_string()));
+        std::fs::remove_file(path).unwrap();
+    }
+}
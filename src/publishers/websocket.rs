@@ -0,0 +1,224 @@
+use crate::interfaces::{Publisher, RawMessage};
+use crate::routing::Route;
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+const INITIAL_BACKOFF_MS: u64 = 1000;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 60000;
+
+type Connection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Serialize, Deserialize)]
+pub struct WebSocket {
+    /// eg. `wss://feed.example.com/v1/stream`
+    pub url: String,
+    /// Sent after every connect, eg. to subscribe to channels. A string is sent
+    /// as is and any other JSON value is serialised
+    pub subscribe_message: Option<serde_json::Value>,
+    /// Upper bound of the delay between reconnect attempts
+    pub max_backoff_ms: Option<u64>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(skip_serializing, skip_deserializing)]
+    connection: Option<Connection>,
+    #[serde(skip_serializing, skip_deserializing)]
+    backoff_ms: u64
+}
+// the connection cannot be shared between threads so a clone only carries the config
+impl Clone for WebSocket {
+    fn clone(&self) -> Self {
+        Self {
+            url: self.url.clone(),
+            subscribe_message: self.subscribe_message.clone(),
+            max_backoff_ms: self.max_backoff_ms,
+            routes: self.routes.clone(),
+            connection: None,
+            backoff_ms: INITIAL_BACKOFF_MS
+        }
+    }
+}
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("url", &self.url)
+            .field("subscribe_message", &self.subscribe_message)
+            .field("max_backoff_ms", &self.max_backoff_ms)
+            .field("routes", &self.routes)
+            .field("connected", &self.connection.is_some())
+            .finish()
+    }
+}
+
+pub struct WebSocketMsg {
+    url: String,
+    msg: String
+}
+impl RawMessage for WebSocketMsg {
+    fn get_content_str(&self) -> String {
+        self.msg.clone()
+    }
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::from([("url".to_string(), self.url.clone())])
+    }
+}
+
+async fn connect(url: String, subscribe_message: Option<serde_json::Value>) -> Result<Connection, String> {
+    let (mut connection, _) = connect_async(url).await.map_err(|e| e.to_string())?;
+    if let Some(subscribe_message) = subscribe_message {
+        let frame = match subscribe_message {
+            serde_json::Value::String(s) => s,
+            other => other.to_string()
+        };
+        connection.send(Message::Text(frame)).await.map_err(|e| e.to_string())?;
+    }
+    Ok(connection)
+}
+
+impl WebSocket {
+    /// Waits before the next connect attempt, doubling the delay each time up to `max_backoff_ms`
+    async fn backoff(&mut self) {
+        let backoff_ms = self.backoff_ms.max(INITIAL_BACKOFF_MS);
+        tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+        self.backoff_ms = (backoff_ms * 2).min(self.max_backoff_ms.unwrap_or(DEFAULT_MAX_BACKOFF_MS));
+    }
+}
+
+#[async_trait]
+impl Publisher for WebSocket {
+    type PubMessage = WebSocketMsg;
+
+    fn repr(&self) -> String {
+        format!("WebSocket {}", self.url)
+    }
+    async fn init(&mut self) {
+        // a server that is down at startup is retried by next_message, like a dropped connection
+        match connect(self.url.clone(), self.subscribe_message.clone()).await {
+            Ok(connection) => self.connection = Some(connection),
+            Err(e) => println!("{}: Failed to connect: {}", self.repr(), e)
+        }
+    }
+    async fn next_message(&mut self) -> Option<WebSocketMsg> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => {
+                self.backoff().await;
+                match connect(self.url.clone(), self.subscribe_message.clone()).await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        println!("{}: Failed to reconnect: {}", self.repr(), e);
+                        return None
+                    }
+                }
+            }
+        };
+        let message = connection.next().await;
+        match message {
+            Some(Ok(Message::Text(msg))) => {
+                self.connection = Some(connection);
+                self.backoff_ms = INITIAL_BACKOFF_MS;
+                Some(WebSocketMsg {url: self.url.clone(), msg})
+            },
+            Some(Ok(Message::Close(frame))) => {
+                println!("{}: Closed by the server: {:?}", self.repr(), frame);
+                None
+            },
+            // pings are answered by the next read, and binary frames are not messages
+            Some(Ok(_)) => {
+                self.connection = Some(connection);
+                None
+            },
+            Some(Err(e)) => {
+                println!("{}: Connection lost: {}", self.repr(), e);
+                None
+            },
+            None => {
+                println!("{}: Connection lost", self.repr());
+                None
+            }
+        }
+    }
+    async fn task_done(&mut self, _message: Self::PubMessage) {}
+    fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Publisher, RawMessage};
+
+    use super::WebSocket;
+    use futures::{SinkExt, StreamExt};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[tokio::test]
+    async fn test_subscribes_and_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut subscriptions = Vec::new();
+            for deployment_name in ["first", "second"] {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut connection = accept_async(stream).await.unwrap();
+                let subscription = connection.next().await.unwrap().unwrap();
+                subscriptions.push(subscription.into_text().unwrap());
+                let data = json!({"flow_name": "Test Flow", "deployment_name": deployment_name}).to_string();
+                connection.send(Message::Text(data)).await.unwrap();
+                connection.close(None).await.unwrap();
+            }
+            subscriptions
+        });
+
+        let mut websocket: WebSocket = serde_json::from_value(json!(
+            {"url": &url, "subscribe_message": {"op": "subscribe", "channel": "events"}}
+        )).unwrap();
+        websocket.init().await;
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            if let Some(msg) = websocket.next_message().await {
+                assert_eq!(msg.get_attributes()["url"], url);
+                received.push(msg.get_content_str());
+                websocket.task_done(msg).await;
+            }
+        }
+        let deployments: Vec<serde_json::Value> = received.iter()
+            .map(|msg| serde_json::from_str::<serde_json::Value>(msg).unwrap()["deployment_name"].clone())
+            .collect();
+        assert_eq!(deployments, vec![json!("first"), json!("second")]);
+        let subscriptions = server.await.unwrap();
+        for subscription in subscriptions {
+            let subscription: serde_json::Value = serde_json::from_str(&subscription).unwrap();
+            assert_eq!(subscription, json!({"op": "subscribe", "channel": "events"}));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connects_once_the_server_is_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let mut websocket: WebSocket = serde_json::from_value(json!({"url": format!("ws://{}", addr)})).unwrap();
+        websocket.init().await;
+        assert!(websocket.connection.is_none());
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = accept_async(stream).await.unwrap();
+            connection.send(Message::Text("{}".to_string())).await.unwrap();
+            let _ = connection.next().await;
+        });
+        let msg = websocket.next_message().await.expect("Should connect and receive the frame");
+        assert_eq!(msg.get_content_str(), "{}");
+    }
+}
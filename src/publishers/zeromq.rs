@@ -1,25 +1,119 @@
 use crate::interfaces::{Publisher, RawMessage};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
 use futures::StreamExt;
-use tmq::{subscribe, Context};
+use tmq::{subscribe, Context, Multipart};
+
+const DEFAULT_RECONNECT_INTERVAL_MS: u64 = 1000;
 
 #[derive(Serialize, Deserialize)]
 pub struct Zmq {
     pub tcp_uri: String,
     pub topic: String,
+    /// `message_type` -> `flow_name/deployment_name` for bodies of the form
+    /// `{"message_type": ..., "payload": ...}`
+    #[serde(default)]
     pub message_flow_actions: HashMap<String, String>,
+    /// Time to wait before re-creating the socket after an error
+    pub reconnect_interval_ms: Option<u64>,
     #[serde(skip_serializing, skip_deserializing)]
     socket: Option<subscribe::Subscribe>
 }
+// the socket cannot be shared between threads so a clone only carries the config
+impl Clone for Zmq {
+    fn clone(&self) -> Self {
+        Self {
+            tcp_uri: self.tcp_uri.clone(),
+            topic: self.topic.clone(),
+            message_flow_actions: self.message_flow_actions.clone(),
+            reconnect_interval_ms: self.reconnect_interval_ms,
+            socket: None
+        }
+    }
+}
+impl fmt::Debug for Zmq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Zmq")
+            .field("tcp_uri", &self.tcp_uri)
+            .field("topic", &self.topic)
+            .field("message_flow_actions", &self.message_flow_actions)
+            .field("reconnect_interval_ms", &self.reconnect_interval_ms)
+            .field("connected", &self.socket.is_some())
+            .finish()
+    }
+}
+
 pub struct ZmqMsg {msg: String}
+impl ZmqMsg {
+    /// Builds a message from the received frames. A multipart message carries the topic
+    /// in its first frame and the body in the rest, whereas a single frame message is
+    /// expected to be prefixed by the subscribed topic.
+    fn from_multipart(multipart: Multipart, subscribed_topic: &str) -> Self {
+        let mut frames: Vec<String> = multipart.iter()
+            .map(|frame| String::from_utf8_lossy(frame).into_owned())
+            .collect();
+        if frames.len() > 1 {
+            Self {msg: frames[1..].concat()}
+        } else {
+            let frame = frames.pop().unwrap_or_default();
+            match frame.strip_prefix(subscribed_topic) {
+                Some(body) => Self {msg: body.trim_start().to_string()},
+                None => Self {msg: frame}
+            }
+        }
+    }
+}
+impl ZmqMsg {
+    /// Rewrites the body into a QMessage if its `message_type` is mapped to a deployment
+    fn with_flow_actions(mut self, message_flow_actions: &HashMap<String, String>) -> Self {
+        if message_flow_actions.is_empty() {
+            return self
+        }
+        let body: serde_json::Value = match serde_json::from_str(&self.msg) {
+            Ok(body) => body,
+            Err(_) => return self
+        };
+        let action = body["message_type"].as_str().and_then(|message_type| message_flow_actions.get(message_type));
+        if let Some((flow_name, deployment_name)) = action.and_then(|action| action.split_once('/')) {
+            self.msg = json!(
+                {"flow_name": flow_name, "deployment_name": deployment_name, "payload": body["payload"]}
+            ).to_string();
+        }
+        self
+    }
+}
 impl RawMessage for ZmqMsg {
     fn get_content_str(&self) -> String {
         self.msg.clone()
     }
 }
 
+impl Zmq {
+    fn connect(&mut self) {
+        let socket_result = subscribe(&Context::new())
+            .connect(self.tcp_uri.as_str())
+            .and_then(|s| s.subscribe(self.topic.as_bytes()));
+        self.socket = match socket_result {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                println!("{}: Unable to connect socket: {:?}", self.repr(), e);
+                None
+            }
+        }
+    }
+    async fn reconnect(&mut self) {
+        self.socket = None;
+        let interval = self.reconnect_interval_ms.unwrap_or(DEFAULT_RECONNECT_INTERVAL_MS);
+        tokio::time::sleep(Duration::from_millis(interval)).await;
+        println!("{}: Reconnecting", self.repr());
+        self.connect()
+    }
+}
+
 #[async_trait]
 impl Publisher for Zmq {
     type PubMessage = ZmqMsg;
@@ -28,94 +122,112 @@ impl Publisher for Zmq {
         format!("ZMQ {}", self.tcp_uri)
     }
     async fn init(&mut self) {
-        let socket: subscribe::Subscribe = subscribe(&Context::new())
-            .connect(self.tcp_uri.as_str())
-            .unwrap()
-            .subscribe(self.topic.as_bytes())
-            .unwrap();
-        self.socket = Some(socket)
+        self.connect()
     }
     async fn next_message(&mut self) -> Option<ZmqMsg> {
-        let next_message = self.socket.as_mut().unwrap().next();
-        let nxt = next_message.await.unwrap();
-        match nxt {
-            Ok(msg) => {
-                let mut full_msg = String::new();
-                let it = msg.iter().map(|x| x.as_str().unwrap());
-                for st in it {
-                    full_msg.push_str(st)
-                };
-                Some(ZmqMsg{msg: full_msg})
+        let next = match self.socket.as_mut() {
+            Some(socket) => socket.next().await,
+            None => {
+                self.reconnect().await;
+                return None
+            }
+        };
+        match next {
+            Some(Ok(multipart)) => Some(
+                ZmqMsg::from_multipart(multipart, &self.topic).with_flow_actions(&self.message_flow_actions)
+            ),
+            Some(Err(e)) => {
+                println!("{}: Got socket error {:?}", self.repr(), e);
+                self.reconnect().await;
+                None
             },
-            Err(e) => {
-                println!("Got error {:?}", e);
+            None => {
+                println!("{}: Socket stream closed", self.repr());
+                self.reconnect().await;
                 None
             }
         }
-
     }
     async fn task_done(&mut self, _message: Self::PubMessage) {}
 }
 
-// // TODO: following test hangs so need to fix
-// #[cfg(test)]
-// mod tests {
-//     use std::collections::HashMap;
-//     use crate::interfaces::Publisher;
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::{Publisher, RawMessage};
 
-//     use super::Zmq;
-//     use serde_json::json;
-//     use std::time::Duration;
-//     use zmq::{Context, SocketType};
-//     use rand::Rng;
-//     use tokio::time::timeout;
+    use super::Zmq;
+    use futures::SinkExt;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tmq::{publish, AsZmqSocket, Context, Multipart};
+    use tokio::time::timeout;
 
-//     fn generate_tcp_address() -> String {
+    fn new_zmq(tcp_uri: String, topic: &str) -> Zmq {
+        Zmq {
+            tcp_uri,
+            topic: String::from(topic),
+            message_flow_actions: HashMap::new(),
+            reconnect_interval_ms: Some(10),
+            socket: None
+        }
+    }
 
-//         let mut rng = rand::thread_rng();
-//         let port = rng.gen_range(2000..65000);
-//         format!("tcp://127.0.0.1:{}", port)
-//     }
+    #[test]
+    fn test_single_frame_strips_topic() {
+        let multipart = Multipart::from(vec!["preftopic {\"a\": 1}"]);
+        let msg = super::ZmqMsg::from_multipart(multipart, "preftopic");
+        assert_eq!(msg.get_content_str(), "{\"a\": 1}");
+    }
 
-//     #[tokio::test]
-//     async fn test_e2e() {
-//         let address = generate_tcp_address();
-//         let pub_sock = Context::new().socket(SocketType::PUB).unwrap();
-//         pub_sock.bind(&address).unwrap();
-//         let topic = "preftopic";
-//         let data = json!(
-//             {"message_type": "MyTestMsg", "payload": {"mytest": "data"}}
-//         ).to_string();
-//         let mut mfa : HashMap<String, String> = HashMap::new();
-//         mfa.insert("MyTestMsg".to_string(), "test/test".to_string());
-//         let mut tzmq = Zmq {
-//             tcp_uri: address,
-//             topic: String::from(topic),
-//             message_flow_actions: mfa,
-//             socket: None
-//         };
-//         let mut data_chcked = false;
-//         for _ in 0usize..5 {
-//             pub_sock.send_multipart(vec![topic.as_bytes(), data.as_bytes()], 0).unwrap();
-//             tzmq.init().await;
-//             assert_eq!(tzmq.get_flow_deployment("MyTestMsg").expect(
-//                 "This should not result in error"
-//             ), ("test".to_string(), "test".to_string()));
-//             // let el_next_msg = timeout(Duration::from_millis(1000), tzmq.next_message()).await;
-//             let next_message = tzmq.next_message().await;
-//             match next_message {
-//                 Some(v) => {
-//                     assert_eq!(v.msg, "data");
-//                     data_chcked = true
-//                 },
-//                 None => panic!("Should have returned a message")
-//             };
-//         }
-//         if ! data_chcked {
-//             panic!("Should not have completed loop without checking data")
-//         }
+    #[test]
+    fn test_maps_message_type_to_deployment() {
+        let actions = HashMap::from([("MyTestMsg".to_string(), "Test Flow/test".to_string())]);
+        let multipart = Multipart::from(vec!["preftopic", "{\"message_type\": \"MyTestMsg\", \"payload\": {\"mytest\": \"data\"}}"]);
+        let msg = super::ZmqMsg::from_multipart(multipart, "preftopic").with_flow_actions(&actions);
+        let content: serde_json::Value = serde_json::from_str(&msg.get_content_str()).unwrap();
+        assert_eq!(content, json!({"flow_name": "Test Flow", "deployment_name": "test", "payload": {"mytest": "data"}}));
 
+        let multipart = Multipart::from(vec!["preftopic", "{\"message_type\": \"Other\"}"]);
+        let msg = super::ZmqMsg::from_multipart(multipart, "preftopic").with_flow_actions(&actions);
+        assert_eq!(msg.get_content_str(), "{\"message_type\": \"Other\"}");
+    }
 
-//     }
+    #[tokio::test]
+    async fn test_e2e() {
+        let mut pub_sock = publish(&Context::new()).bind("tcp://127.0.0.1:*").unwrap();
+        let address = pub_sock.get_socket().get_last_endpoint().unwrap().unwrap();
+        let topic = "preftopic";
+        let data = json!(
+            {"flow_name": "Test Flow", "deployment_name": "test", "payload": {"mytest": "data"}}
+        ).to_string();
+        let mut tzmq = new_zmq(address, topic);
+        tzmq.init().await;
 
-// }
+        // PUB drops messages until the subscription has propagated so keep
+        // sending until the subscriber picks one up
+        let received = timeout(Duration::from_secs(10), async {
+            loop {
+                pub_sock.send(vec![topic, data.as_str()]).await.unwrap();
+                let next = timeout(Duration::from_millis(100), tzmq.next_message()).await;
+                if let Ok(Some(msg)) = next {
+                    return msg
+                }
+            }
+        }).await.expect("Subscriber did not receive a message in time");
+        assert_eq!(received.get_content_str(), data);
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_without_socket() {
+        let mut tzmq = new_zmq("not-a-valid-endpoint".to_string(), "preftopic");
+        tzmq.init().await;
+        assert!(tzmq.socket.is_none());
+        assert!(tzmq.next_message().await.is_none());
+        // SUB sockets connect lazily so any free port will do
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        tzmq.tcp_uri = format!("tcp://127.0.0.1:{}", port);
+        assert!(tzmq.next_message().await.is_none());
+        assert!(tzmq.socket.is_some());
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::interfaces::{QMessage, RawMessage};
+
+/// A rule mapping messages onto a deployment from the attributes the source
+/// attached to them (eg. the topic it was received on) so that the message
+/// body does not need to be a full QMessage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    /// attribute name -> expected value. A trailing `*` matches any suffix
+    #[serde(rename = "match")]
+    pub match_attributes: HashMap<String, String>,
+    pub flow_name: String,
+    pub deployment_name: String
+}
+impl Route {
+    pub fn matches(&self, attributes: &HashMap<String, String>) -> bool {
+        self.match_attributes.iter().all(|(key, pattern)| {
+            match attributes.get(key) {
+                Some(value) => match pattern.strip_suffix('*') {
+                    Some(prefix) => value.starts_with(prefix),
+                    None => value == pattern
+                },
+                None => false
+            }
+        })
+    }
+}
+
+/// Builds the QMessage for a raw message. The first route whose match applies decides
+/// the deployment and the content becomes its payload; when no route matches the
+/// content itself must be a QMessage
+pub fn to_q_message(
+    routes: &[Route], message: &impl RawMessage
+) -> Result<QMessage, serde_json::Error> {
+    let content = message.get_content_str();
+    if !routes.is_empty() {
+        let attributes = message.get_attributes();
+        if let Some(route) = routes.iter().find(|r| r.matches(&attributes)) {
+            let payload = if content.trim().is_empty() {
+                None
+            } else {
+                Some(serde_json::from_str(&content).unwrap_or(serde_json::Value::String(content)))
+            };
+            return Ok(QMessage::new(route.flow_name.clone(), route.deployment_name.clone(), payload))
+        }
+    }
+    serde_json::from_str(&content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use serde_json::json;
+
+    use super::{to_q_message, Route};
+    use crate::interfaces::RawMessage;
+
+    struct TestMsg {
+        content: String,
+        topic: String
+    }
+    impl RawMessage for TestMsg {
+        fn get_content_str(&self) -> String {
+            self.content.clone()
+        }
+        fn get_attributes(&self) -> HashMap<String, String> {
+            HashMap::from([("topic".to_string(), self.topic.clone())])
+        }
+    }
+
+    fn sensor_route() -> Route {
+        serde_json::from_value(json!(
+            {
+                "match": {"topic": "sensors/*"},
+                "flow_name": "Sensor Flow",
+                "deployment_name": "sensors"
+            }
+        )).expect("Failed to load route")
+    }
+
+    #[test]
+    fn test_route_matches_wildcard() {
+        let route = sensor_route();
+        let attrs = HashMap::from([("topic".to_string(), "sensors/a/temp".to_string())]);
+        assert!(route.matches(&attrs));
+        let attrs = HashMap::from([("topic".to_string(), "alerts/a".to_string())]);
+        assert!(!route.matches(&attrs));
+        assert!(!route.matches(&HashMap::new()));
+    }
+
+    #[test]
+    fn test_routed_content_becomes_payload() {
+        let msg = TestMsg {
+            content: json!({"temp": 21}).to_string(), topic: "sensors/a/temp".to_string()
+        };
+        let q_message = to_q_message(&[sensor_route()], &msg).expect("Should route the message");
+        assert_eq!(q_message.get_flow_deployment(), ("Sensor Flow".to_string(), "sensors".to_string()));
+        assert_eq!(q_message.get_flow_parameters().as_ref().unwrap()["temp"], 21);
+    }
+
+    #[test]
+    fn test_unrouted_content_must_be_q_message() {
+        let msg = TestMsg {
+            content: json!({"flow_name": "Test Flow", "deployment_name": "test"}).to_string(),
+            topic: "alerts/a".to_string()
+        };
+        let q_message = to_q_message(&[sensor_route()], &msg).expect("Should parse the QMessage");
+        assert_eq!(q_message.get_flow_deployment(), ("Test Flow".to_string(), "test".to_string()));
+
+        let msg = TestMsg {content: json!({"temp": 21}).to_string(), topic: "alerts/a".to_string()};
+        assert!(to_q_message(&[sensor_route()], &msg).is_err());
+    }
+}